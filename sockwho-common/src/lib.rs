@@ -1,5 +1,8 @@
 #![cfg_attr(not(feature = "user"), no_std)]
 
+/// The maximum length of a path in a Unix domain socket address.
+pub const UNIX_PATH_MAX: usize = 108;
+
 #[derive(Clone, Debug, Copy)]
 #[repr(C)]
 pub struct SockaddrEvent {
@@ -9,9 +12,25 @@ pub struct SockaddrEvent {
     pub port: u16,
     pub family: AddressFamily,
     pub syscall: Syscall,
-    pub _padding: u32,
+    pub local_port: u16,
+    pub implicit_peer: bool,
+    pub path_length: u8,
+    pub local_address: [u8; 16],
     pub command: [u8; 16],
     pub return_value: i64,
+    pub timestamp: u64,
+    pub duration: u64,
+    pub cgroup_id: u64,
+    /// When the process started, in nanoseconds since boot, or 0 if it isn't known.
+    pub start_time: u64,
+    pub path: [u8; UNIX_PATH_MAX],
+    pub tid: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// The pid of the process' parent, or 0 if it isn't known.
+    pub ppid: u32,
+    /// The inode of the network namespace the process is in, or 0 if it isn't known.
+    pub net_namespace: u32,
 }
 
 #[derive(Clone, Debug)]
@@ -27,6 +46,248 @@ pub struct SocketStateEvent {
     pub src_address: [u8; 16],
     pub dst_address: [u8; 16],
     pub command: [u8; 16],
+    pub tid: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// The pid of the process' parent, or 0 if it isn't known.
+    pub ppid: u32,
+    /// The inode of the network namespace the socket is in, which isn't necessarily the one of the process given state
+    /// changes can happen in softirq context, or 0 if it isn't known.
+    pub net_namespace: u32,
+    pub timestamp: u64,
+    pub cgroup_id: u64,
+    /// When the process started, in nanoseconds since boot, or 0 if it isn't known.
+    pub start_time: u64,
+}
+
+#[derive(Clone, Debug, Copy)]
+#[repr(C)]
+pub struct SocketCreatedEvent {
+    pub pid: u32,
+    pub domain: u32,
+    pub socket_type: u32,
+    pub protocol: u32,
+    pub tid: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// The pid of the process' parent, or 0 if it isn't known.
+    pub ppid: u32,
+    /// The inode of the network namespace the process is in, or 0 if it isn't known.
+    pub net_namespace: u32,
+    pub _padding: u32,
+    pub command: [u8; 16],
+    pub return_value: i64,
+    pub timestamp: u64,
+    pub duration: u64,
+    pub cgroup_id: u64,
+    /// When the process started, in nanoseconds since boot, or 0 if it isn't known.
+    pub start_time: u64,
+}
+
+#[derive(Clone, Debug, Copy)]
+#[repr(C)]
+pub struct SocketCallEvent {
+    pub pid: u32,
+    pub fd: u32,
+    pub syscall: Syscall,
+    pub _padding: [u8; 3],
+    pub tid: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// The pid of the process' parent, or 0 if it isn't known.
+    pub ppid: u32,
+    /// The inode of the network namespace the process is in, or 0 if it isn't known.
+    pub net_namespace: u32,
+    pub argument: i64,
+    pub command: [u8; 16],
+    pub return_value: i64,
+    pub timestamp: u64,
+    pub duration: u64,
+    pub cgroup_id: u64,
+    /// When the process started, in nanoseconds since boot, or 0 if it isn't known.
+    pub start_time: u64,
+}
+
+/// The maximum number of bytes of a socket option's value that are captured.
+pub const OPTION_VALUE_MAX: usize = 16;
+
+#[derive(Clone, Debug, Copy)]
+#[repr(C)]
+pub struct SocketOptionEvent {
+    pub pid: u32,
+    pub fd: u32,
+    pub level: i32,
+    pub name: i32,
+    pub value_length: u32,
+    pub value: [u8; OPTION_VALUE_MAX],
+    pub command: [u8; 16],
+    pub tid: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// The pid of the process' parent, or 0 if it isn't known.
+    pub ppid: u32,
+    /// The inode of the network namespace the process is in, or 0 if it isn't known.
+    pub net_namespace: u32,
+    pub return_value: i64,
+    pub timestamp: u64,
+    pub duration: u64,
+    pub cgroup_id: u64,
+    /// When the process started, in nanoseconds since boot, or 0 if it isn't known.
+    pub start_time: u64,
+}
+
+/// The maximum length of the path of an executable that's captured.
+pub const EXEC_PATH_MAX: usize = 256;
+
+#[derive(Clone, Debug, Copy)]
+#[repr(C)]
+pub struct ProcessExecEvent {
+    pub pid: u32,
+    pub path_length: u32,
+    pub timestamp: u64,
+    pub cgroup_id: u64,
+    /// When the process started, in nanoseconds since boot, or 0 if it isn't known.
+    pub start_time: u64,
+    pub command: [u8; 16],
+    pub path: [u8; EXEC_PATH_MAX],
+}
+
+/// Filter events by the pid of the process that generated them.
+pub const FILTER_PID: u8 = 1 << 0;
+
+/// Filter events by the name of the process that generated them.
+pub const FILTER_COMMAND: u8 = 1 << 1;
+
+/// Filter events by the ports they involve.
+pub const FILTER_PORT: u8 = 1 << 2;
+
+/// Filter events by the networks the addresses they involve belong to.
+pub const FILTER_NETWORK: u8 = 1 << 3;
+
+/// Also filter events by the pids of the descendants of the processes in the pid filter, which are added to it as
+/// they're created.
+pub const FILTER_FOLLOW_CHILDREN: u8 = 1 << 4;
+
+/// The maximum number of entries in the map used to summarize events in the kernel.
+pub const SUMMARY_MAX_ENTRIES: u32 = 16384;
+
+/// The values shared by the events that are counted together when summarizing in the kernel.
+#[derive(Clone, Debug, Copy)]
+#[repr(C)]
+pub struct SummaryKey {
+    pub command: [u8; 16],
+    pub address: [u8; 16],
+    /// The error the syscall failed with, or 0 if it succeeded.
+    pub errno: i32,
+    pub port: u16,
+    pub family: AddressFamily,
+    pub syscall: Syscall,
+    /// The interval the events were counted in, so userspace can remove the entries of past ones without racing with
+    /// the eBPF programs.
+    pub generation: u32,
+}
+
+/// How many times the events with the same `SummaryKey` happened, and when the first and last ones did.
+#[derive(Clone, Debug, Copy)]
+#[repr(C)]
+pub struct SummaryValue {
+    pub count: u64,
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+/// Where the fields of the kernel structs read by the eBPF programs are, in bytes from the start of the struct. This
+/// depends on the kernel's version and configuration, so userspace finds them in the kernel's BTF when loading the
+/// programs.
+#[derive(Clone, Debug, Copy, Default)]
+#[repr(C)]
+pub struct KernelOffsets {
+    /// `sk` in `struct socket`.
+    pub socket_sk: u32,
+    /// The fields of the `struct sock_common` that every `struct sock` starts with.
+    pub sock_daddr: u32,
+    pub sock_rcv_saddr: u32,
+    pub sock_dport: u32,
+    pub sock_num: u32,
+    pub sock_family: u32,
+    pub sock_v6_daddr: u32,
+    pub sock_v6_rcv_saddr: u32,
+    /// `skc_net` in `struct sock_common`, or 0 if the kernel was built without network namespaces.
+    pub sock_net: u32,
+    /// `real_parent`, `tgid`, `group_leader` and `start_boottime` in `struct task_struct`.
+    pub task_real_parent: u32,
+    pub task_tgid: u32,
+    pub task_group_leader: u32,
+    pub task_start_time: u32,
+    /// `nsproxy` in `struct task_struct`, `net_ns` in `struct nsproxy` and `ns.inum` in `struct net`.
+    pub task_nsproxy: u32,
+    pub nsproxy_net_ns: u32,
+    pub net_inum: u32,
+}
+
+/// Where the fields of the `task_newtask` tracepoint read by the eBPF programs are, in bytes from the start of its
+/// record. Unlike the ones of the syscall tracepoints, its layout isn't part of the kernel's ABI so userspace reads it
+/// from tracefs when loading the programs.
+#[derive(Clone, Debug, Copy, Default)]
+#[repr(C)]
+pub struct NewTaskOffsets {
+    pub pid: u32,
+    pub clone_flags: u32,
+}
+
+/// The size of the ring buffer used to send events to userspace, in bytes.
+pub const RING_BUFFER_SIZE: u32 = 8 * 1024 * 1024;
+
+/// The size of the header that precedes the event in a ring buffer record.
+pub const RING_BUFFER_HEADER_SIZE: usize = 8;
+
+/// The kind of event stored in a ring buffer record.
+#[derive(Clone, Debug, Copy)]
+#[repr(u32)]
+pub enum EventKind {
+    Sockaddr,
+    SocketState,
+    SocketCreated,
+    SocketCall,
+    SocketOption,
+    ProcessExec,
+}
+
+/// An event sent to userspace.
+pub trait Event {
+    const KIND: EventKind;
+}
+
+impl Event for SockaddrEvent {
+    const KIND: EventKind = EventKind::Sockaddr;
+}
+
+impl Event for SocketStateEvent {
+    const KIND: EventKind = EventKind::SocketState;
+}
+
+impl Event for SocketCreatedEvent {
+    const KIND: EventKind = EventKind::SocketCreated;
+}
+
+impl Event for SocketCallEvent {
+    const KIND: EventKind = EventKind::SocketCall;
+}
+
+impl Event for SocketOptionEvent {
+    const KIND: EventKind = EventKind::SocketOption;
+}
+
+impl Event for ProcessExecEvent {
+    const KIND: EventKind = EventKind::ProcessExec;
+}
+
+/// A record in the ring buffer. All kinds of events share the same ring buffer so each one is tagged with its kind.
+#[repr(C)]
+pub struct RingBufferRecord<T> {
+    pub kind: EventKind,
+    pub _padding: u32,
+    pub event: T,
 }
 
 #[derive(Clone, Debug, Copy)]
@@ -34,6 +295,9 @@ pub struct SocketStateEvent {
 pub enum AddressFamily {
     Ipv4,
     Ipv6,
+    Unix,
+    /// No address is known, e.g. for accept(2) calls that weren't given a sockaddr or that failed.
+    Unspecified,
 }
 
 #[derive(Clone, Debug, Copy)]
@@ -44,6 +308,16 @@ pub enum Syscall {
     Connect,
     RecvFrom,
     SendTo,
+    Accept,
+    SendMsg,
+    RecvMsg,
+    SendMmsg,
+    RecvMmsg,
+    Listen,
+    Shutdown,
+    Close,
+    GetSockName,
+    GetPeerName,
 }
 
 pub struct HandlerError(i32);
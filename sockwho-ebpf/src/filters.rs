@@ -0,0 +1,107 @@
+use crate::utils::as_pid;
+use aya_bpf::{
+    helpers::{bpf_get_current_comm, bpf_get_current_pid_tgid},
+    macros::map,
+    maps::{
+        lpm_trie::{Key, LpmTrie},
+        HashMap,
+    },
+};
+use core::ptr::read_volatile;
+use sockwho_common::{AddressFamily, FILTER_COMMAND, FILTER_FOLLOW_CHILDREN, FILTER_NETWORK, FILTER_PID, FILTER_PORT};
+
+// The filters that are in use, as a combination of the `FILTER_*` flags. This is set by userspace when loading the
+// programs, which lets the verifier skip the lookups for the filters that aren't used.
+#[no_mangle]
+static FILTERS: u8 = 0;
+
+// Sized for the descendants of the processes that are followed, not only for the ones given by userspace.
+#[map]
+static mut FILTER_PIDS: HashMap<u32, u8> = HashMap::with_max_entries(8192, 0);
+
+#[map]
+static mut FILTER_COMMANDS: HashMap<[u8; 16], u8> = HashMap::with_max_entries(1024, 0);
+
+// Ports are stored in host byte order.
+#[map]
+static mut FILTER_PORTS: HashMap<u16, u8> = HashMap::with_max_entries(1024, 0);
+
+// IPv4 networks are stored as IPv4-mapped IPv6 ones so both families can share the same trie. LPM tries can't be
+// preallocated, hence the BPF_F_NO_PREALLOC flag.
+#[map]
+static mut FILTER_NETWORKS: LpmTrie<[u8; 16], u8> = LpmTrie::with_max_entries(1024, 1);
+
+fn enabled(filter: u8) -> bool {
+    let filters = unsafe { read_volatile(&FILTERS) };
+    filters & filter != 0
+}
+
+/// Whether the events generated by the current process should be sent to userspace.
+pub(crate) fn process_allowed() -> bool {
+    if enabled(FILTER_PID) {
+        let pid = as_pid(bpf_get_current_pid_tgid());
+        if unsafe { FILTER_PIDS.get(&pid) }.is_none() {
+            return false;
+        }
+    }
+    if enabled(FILTER_COMMAND) {
+        let Ok(command) = bpf_get_current_comm() else {
+            return false;
+        };
+        if unsafe { FILTER_COMMANDS.get(&command) }.is_none() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Adds a process that was just created by the current one to the pid filter, if the current one is in it and its
+/// descendants are being followed.
+pub(crate) fn follow_child(child_pid: u32) {
+    if !enabled(FILTER_FOLLOW_CHILDREN) {
+        return;
+    }
+    let pid = as_pid(bpf_get_current_pid_tgid());
+    if unsafe { FILTER_PIDS.get(&pid) }.is_some() {
+        // This is best effort: at worst the child's events won't be traced.
+        let _ = unsafe { FILTER_PIDS.insert(&child_pid, &1, 0) };
+    }
+}
+
+/// Removes a process that's exiting from the pid filter, so its pid can't be reused by an unrelated process
+/// that would then be traced.
+pub(crate) fn forget_process(pid: u32) {
+    if enabled(FILTER_FOLLOW_CHILDREN) {
+        let _ = unsafe { FILTER_PIDS.remove(&pid) };
+    }
+}
+
+/// Whether any addressing filter is in use, in which case events that don't involve an address are only sent for
+/// sockets that were already found to match them.
+pub(crate) fn addressing_filtered() -> bool {
+    enabled(FILTER_PORT | FILTER_NETWORK)
+}
+
+/// Whether an event that involves an address and port, stored the same way as in events, should be sent to userspace.
+pub(crate) fn endpoint_allowed(family: &AddressFamily, address: &[u8; 16], port: u16) -> bool {
+    port_allowed(port) && address_allowed(family, address)
+}
+
+fn port_allowed(port: u16) -> bool {
+    !enabled(FILTER_PORT) || unsafe { FILTER_PORTS.get(&u16::from_be(port)) }.is_some()
+}
+
+fn address_allowed(family: &AddressFamily, address: &[u8; 16]) -> bool {
+    if !enabled(FILTER_NETWORK) {
+        return true;
+    }
+    let data = match family {
+        AddressFamily::Ipv4 => {
+            let a = address;
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a[0], a[1], a[2], a[3]]
+        }
+        AddressFamily::Ipv6 => *address,
+        AddressFamily::Unix | AddressFamily::Unspecified => return false,
+    };
+    unsafe { FILTER_NETWORKS.get(&Key::new(128, data)) }.is_some()
+}
@@ -0,0 +1,64 @@
+use crate::{
+    tracepoints::{parse_family, PendingAddress, PID_EVENT},
+    utils::{ipv4_address, kernel_offsets, read_kernel},
+};
+use aya_bpf::{helpers::bpf_get_current_pid_tgid, programs::ProbeContext};
+use sockwho_common::{AddressFamily, HandlerResult};
+use sockwho_macros::sockwho_kprobe;
+
+#[sockwho_kprobe]
+fn inet_sendmsg(ctx: ProbeContext) -> HandlerResult {
+    resolve_peer(ctx)
+}
+
+#[sockwho_kprobe]
+fn inet6_sendmsg(ctx: ProbeContext) -> HandlerResult {
+    resolve_peer(ctx)
+}
+
+#[sockwho_kprobe]
+fn inet_recvmsg(ctx: ProbeContext) -> HandlerResult {
+    resolve_peer(ctx)
+}
+
+#[sockwho_kprobe]
+fn inet6_recvmsg(ctx: ProbeContext) -> HandlerResult {
+    resolve_peer(ctx)
+}
+
+/// Fills in the local and peer addresses of a syscall on a connected socket that was given no sockaddr.
+fn resolve_peer(ctx: ProbeContext) -> HandlerResult {
+    let pid = bpf_get_current_pid_tgid();
+    let pending = match unsafe { PID_EVENT.get_ptr_mut(&pid) } {
+        Some(pending) => unsafe { &mut *pending },
+        None => return Ok(()),
+    };
+    if !matches!(pending.address, PendingAddress::Peer { resolved: false }) {
+        return Ok(());
+    }
+    let offsets = kernel_offsets();
+    let socket: *const u8 = ctx.arg(0).ok_or(1)?;
+    let sock: *const u8 = read_kernel(socket, offsets.socket_sk)?;
+    let family = parse_family(read_kernel(sock, offsets.sock_family)?)?;
+
+    let event = &mut pending.event;
+    match family {
+        AddressFamily::Ipv4 => {
+            event.address = ipv4_address(read_kernel(sock, offsets.sock_daddr)?);
+            event.local_address = ipv4_address(read_kernel(sock, offsets.sock_rcv_saddr)?);
+        }
+        AddressFamily::Ipv6 => {
+            event.address = read_kernel(sock, offsets.sock_v6_daddr)?;
+            event.local_address = read_kernel(sock, offsets.sock_v6_rcv_saddr)?;
+        }
+        AddressFamily::Unix | AddressFamily::Unspecified => return Ok(()),
+    };
+    event.port = read_kernel(sock, offsets.sock_dport)?;
+    // Unlike the peer's, the local port is stored in host byte order.
+    event.local_port = read_kernel::<u16>(sock, offsets.sock_num)?.to_be();
+    event.family = family;
+    event.implicit_peer = true;
+    pending.address = PendingAddress::Peer { resolved: true };
+
+    Ok(())
+}
@@ -2,6 +2,10 @@
 #![no_main]
 
 mod context;
+mod filters;
+mod kprobes;
+mod output;
+mod summary;
 mod tracepoints;
 mod utils;
 
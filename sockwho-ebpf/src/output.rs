@@ -0,0 +1,44 @@
+use aya_bpf::{
+    macros::map,
+    maps::{PerCpuArray, PerfEventArray, RingBuf},
+    BpfContext,
+};
+use core::ptr::{addr_of_mut, copy_nonoverlapping, read_volatile};
+use sockwho_common::{Event, RingBufferRecord, RING_BUFFER_SIZE};
+
+// Whether events are sent through the ring buffer rather than through each event type's perf event array. This is set
+// by userspace when loading the programs, which lets the verifier skip the path that isn't used.
+#[no_mangle]
+static USE_RING_BUFFER: u8 = 0;
+
+// This is pinned so userspace can get a handle to it and so it can be replaced by a placeholder on kernels that don't
+// support ring buffers.
+#[map]
+static mut EVENTS: RingBuf = RingBuf::pinned(RING_BUFFER_SIZE, 0);
+
+// The number of events that couldn't be sent through the ring buffer because it was full, which userspace reports the
+// same way as the events lost by perf event arrays.
+#[map]
+static mut DROPPED_EVENTS: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+/// Sends an event to userspace.
+pub(crate) fn output<C: BpfContext, T: Event + 'static>(ctx: &C, events: &mut PerfEventArray<T>, event: &T) {
+    if unsafe { read_volatile(&USE_RING_BUFFER) } == 0 {
+        events.output(ctx, event, 0);
+        return;
+    }
+    // Some events are too large to be built on the stack so they're copied straight into the ring buffer.
+    let Some(mut entry) = (unsafe { EVENTS.reserve::<RingBufferRecord<T>>(0) }) else {
+        if let Some(dropped) = unsafe { DROPPED_EVENTS.get_ptr_mut(0) } {
+            unsafe { *dropped += 1 };
+        }
+        return;
+    };
+    let record = entry.as_mut_ptr();
+    unsafe {
+        addr_of_mut!((*record).kind).write(T::KIND);
+        addr_of_mut!((*record)._padding).write(0);
+        copy_nonoverlapping(event, addr_of_mut!((*record).event), 1);
+    }
+    entry.submit(0);
+}
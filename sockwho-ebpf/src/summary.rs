@@ -0,0 +1,71 @@
+use aya_bpf::{
+    macros::map,
+    maps::{Array, HashMap},
+};
+use core::{
+    ptr::{addr_of_mut, read_volatile},
+    sync::atomic::{AtomicU64, Ordering},
+};
+use sockwho_common::{AddressFamily, SockaddrEvent, SummaryKey, SummaryValue, SUMMARY_MAX_ENTRIES};
+
+// Only insert entries that don't exist yet, so one created concurrently by another CPU isn't overwritten.
+const BPF_NOEXIST: u64 = 1;
+
+// Whether events are counted in the summary map rather than sent to userspace. This is set by userspace when loading
+// the programs.
+#[no_mangle]
+static SUMMARIZE: u8 = 0;
+
+// Userspace reads this map periodically and removes the entries of the past generations.
+#[map]
+static mut SUMMARY: HashMap<SummaryKey, SummaryValue> = HashMap::with_max_entries(SUMMARY_MAX_ENTRIES, 0);
+
+// The generation events are counted in, which userspace increments every time it reads the summary.
+#[map]
+static mut SUMMARY_GENERATION: Array<u32> = Array::with_max_entries(1, 0);
+
+/// Counts an event in the summary map, returning whether it was counted. Events that aren't counted, e.g. because
+/// summarizing is disabled or the map is full, should be sent to userspace instead.
+pub(crate) fn summarize(event: &SockaddrEvent) -> bool {
+    if unsafe { read_volatile(&SUMMARIZE) } == 0 {
+        return false;
+    }
+    // Unix socket paths are too large to be part of the key, and events without an address are grouped by userspace.
+    if let AddressFamily::Unix | AddressFamily::Unspecified = event.family {
+        return false;
+    }
+    let errno = match event.return_value {
+        value if value < 0 => -value as i32,
+        _ => 0,
+    };
+    let key = SummaryKey {
+        command: event.command,
+        address: event.address,
+        errno,
+        port: event.port,
+        family: event.family,
+        syscall: event.syscall,
+        generation: unsafe { SUMMARY_GENERATION.get(0) }.copied().unwrap_or_default(),
+    };
+    if count(&key, event.timestamp) {
+        return true;
+    }
+    let value = SummaryValue { count: 1, first_seen: event.timestamp, last_seen: event.timestamp };
+    if unsafe { SUMMARY.insert(&key, &value, BPF_NOEXIST) }.is_ok() {
+        return true;
+    }
+    // Another CPU may have just inserted it.
+    count(&key, event.timestamp)
+}
+
+/// Counts an event in an existing entry, returning whether there was one.
+fn count(key: &SummaryKey, timestamp: u64) -> bool {
+    let Some(value) = (unsafe { SUMMARY.get_ptr_mut(key) }) else {
+        return false;
+    };
+    unsafe {
+        AtomicU64::from_ptr(addr_of_mut!((*value).count)).fetch_add(1, Ordering::Relaxed);
+        (*value).last_seen = timestamp;
+    }
+    true
+}
@@ -1,11 +1,28 @@
-use crate::{context::ReadField, utils::as_pid};
+use crate::{
+    context::ReadField,
+    filters::{addressing_filtered, endpoint_allowed, follow_child, forget_process, process_allowed},
+    output::output,
+    summary::summarize,
+    utils::{
+        as_pid, as_tid, current_cgroup, current_credentials, current_net_namespace, current_parent, current_start_time,
+        finish_syscall, ipv4_address, now, socket_net_namespace,
+    },
+};
 use aya_bpf::{
-    helpers::{bpf_get_current_comm, bpf_get_current_pid_tgid, bpf_probe_read_user},
+    helpers::{
+        bpf_get_current_comm, bpf_get_current_pid_tgid, bpf_probe_read_kernel_str_bytes, bpf_probe_read_user,
+        bpf_probe_read_user_buf,
+    },
     macros::map,
-    maps::{HashMap, PerfEventArray},
+    maps::{HashMap, LruHashMap, PerCpuArray, PerfEventArray},
     programs::TracePointContext,
+    BpfContext,
+};
+use core::{mem::size_of, ptr::read_volatile};
+use sockwho_common::{
+    AddressFamily, HandlerResult, NewTaskOffsets, ProcessExecEvent, SockaddrEvent, SocketCallEvent, SocketCreatedEvent,
+    SocketOptionEvent, SocketStateEvent, Syscall, OPTION_VALUE_MAX, UNIX_PATH_MAX,
 };
-use sockwho_common::{AddressFamily, HandlerResult, SockaddrEvent, SocketStateEvent, Syscall};
 use sockwho_macros::sockwho_tracepoint;
 
 #[map]
@@ -15,7 +32,60 @@ static mut SOCKADDR_EVENTS: PerfEventArray<SockaddrEvent> = PerfEventArray::new(
 static mut SOCKET_STATE_EVENTS: PerfEventArray<SocketStateEvent> = PerfEventArray::new(0);
 
 #[map]
-static mut PID_EVENT: HashMap<u64, SockaddrEvent> = HashMap::with_max_entries(1024, 0);
+static mut SOCKET_CREATED_EVENTS: PerfEventArray<SocketCreatedEvent> = PerfEventArray::new(0);
+
+#[map]
+static mut PID_SOCKET: HashMap<u64, SocketCreatedEvent> = HashMap::with_max_entries(1024, 0);
+
+#[map]
+static mut SOCKET_CALL_EVENTS: PerfEventArray<SocketCallEvent> = PerfEventArray::new(0);
+
+#[map]
+static mut PID_CALL: HashMap<u64, SocketCallEvent> = HashMap::with_max_entries(1024, 0);
+
+#[map]
+static mut SOCKET_OPTION_EVENTS: PerfEventArray<SocketOptionEvent> = PerfEventArray::new(0);
+
+#[map]
+static mut PID_OPTION: HashMap<u64, SocketOptionEvent> = HashMap::with_max_entries(1024, 0);
+
+#[map]
+static mut PROCESS_EXEC_EVENTS: PerfEventArray<ProcessExecEvent> = PerfEventArray::new(0);
+
+// Exec events are too large to be built on the stack.
+#[map]
+static mut EXEC_EVENT: PerCpuArray<ProcessExecEvent> = PerCpuArray::with_max_entries(1, 0);
+
+// The file descriptors known to be sockets, used to only trace close(2) calls on sockets. The value is set to 1 once the
+// socket is found to match the addressing filters.
+#[map]
+static mut SOCKET_FDS: LruHashMap<u64, u8> = LruHashMap::with_max_entries(65536, 0);
+
+#[map]
+pub(crate) static mut PID_EVENT: HashMap<u64, PendingEvent> = HashMap::with_max_entries(1024, 0);
+
+/// An event that's waiting for its syscall to return.
+pub(crate) struct PendingEvent {
+    pub(crate) event: SockaddrEvent,
+    pub(crate) address: PendingAddress,
+}
+
+/// Where the socket address for a pending event comes from.
+pub(crate) enum PendingAddress {
+    /// A sockaddr passed in to the syscall, which is decoded when the syscall is entered.
+    Input { sockaddr: *const u8, length: i32 },
+
+    /// A sockaddr that the kernel fills in, which can only be decoded once the syscall returns. The length is also
+    /// filled in by the kernel and indicates how much of the sockaddr was actually written.
+    Output { sockaddr: *const u8, length: *const i32 },
+
+    /// An array of mmsghdr structs. Only the entries that were processed are decoded once the syscall returns.
+    Messages(*const MmsgHdr),
+
+    /// No sockaddr was provided because the socket is connected. The peer is resolved from the socket itself by the
+    /// inet layer kprobes while the syscall runs.
+    Peer { resolved: bool },
+}
 
 #[repr(C)]
 struct SockaddrIn {
@@ -32,19 +102,52 @@ struct SockaddrIn6 {
     address: [u8; 16],
 }
 
+#[repr(C)]
+struct MsgHdr {
+    name: *const u8,
+    name_length: i32,
+    iov: *const u8,
+    iov_length: usize,
+    control: *const u8,
+    control_length: usize,
+    flags: u32,
+}
+
+#[repr(C)]
+pub(crate) struct MmsgHdr {
+    header: MsgHdr,
+    length: u32,
+}
+
 struct ArgumentOffsets {
     fd: usize,
     sockaddr: usize,
+    sockaddr_length: usize,
+}
+
+struct MessageOffsets {
+    fd: usize,
+    msghdr: usize,
 }
 
+// The flag used to only insert an element into a map if it's not already there.
+const BPF_NOEXIST: u64 = 1;
+
+const AF_UNIX: u16 = 1;
 const AF_INET: u16 = 2;
 const AF_INET6: u16 = 10;
 
-// Control (bind/connect) syscall offsets.
-static CONTROL_OFFSETS: ArgumentOffsets = ArgumentOffsets { fd: 16, sockaddr: 24 };
+// Control (bind/connect/accept/getsockname/getpeername) syscall offsets.
+static CONTROL_OFFSETS: ArgumentOffsets = ArgumentOffsets { fd: 16, sockaddr: 24, sockaddr_length: 32 };
 
 // IO (recvfrom/sendto) syscall offsets.
-static IO_OFFSETS: ArgumentOffsets = ArgumentOffsets { fd: 16, sockaddr: 48 };
+static IO_OFFSETS: ArgumentOffsets = ArgumentOffsets { fd: 16, sockaddr: 48, sockaddr_length: 56 };
+
+// Message (sendmsg/recvmsg/sendmmsg/recvmmsg) syscall offsets.
+static MSG_OFFSETS: MessageOffsets = MessageOffsets { fd: 16, msghdr: 24 };
+
+// The maximum number of entries decoded for a single sendmmsg/recvmmsg call.
+const MAX_MMSG_ENTRIES: usize = 8;
 
 #[sockwho_tracepoint]
 fn sys_enter_bind(ctx: TracePointContext) -> HandlerResult {
@@ -58,14 +161,169 @@ fn sys_enter_connect(ctx: TracePointContext) -> HandlerResult {
 
 #[sockwho_tracepoint]
 fn sys_enter_recvfrom(ctx: TracePointContext) -> HandlerResult {
-    syscall_enter(ctx, &IO_OFFSETS, Syscall::RecvFrom)
+    let sockaddr: *const u8 = ctx.read_field(IO_OFFSETS.sockaddr)?;
+    if sockaddr.is_null() {
+        return syscall_enter_connected(ctx, &IO_OFFSETS, Syscall::RecvFrom);
+    }
+    syscall_enter_deferred(ctx, &IO_OFFSETS, Syscall::RecvFrom)
 }
 
 #[sockwho_tracepoint]
 fn sys_enter_sendto(ctx: TracePointContext) -> HandlerResult {
+    let sockaddr: *const u8 = ctx.read_field(IO_OFFSETS.sockaddr)?;
+    if sockaddr.is_null() {
+        return syscall_enter_connected(ctx, &IO_OFFSETS, Syscall::SendTo);
+    }
     syscall_enter(ctx, &IO_OFFSETS, Syscall::SendTo)
 }
 
+#[sockwho_tracepoint]
+fn sys_enter_accept(ctx: TracePointContext) -> HandlerResult {
+    syscall_enter_deferred(ctx, &CONTROL_OFFSETS, Syscall::Accept)
+}
+
+#[sockwho_tracepoint]
+fn sys_enter_accept4(ctx: TracePointContext) -> HandlerResult {
+    syscall_enter_deferred(ctx, &CONTROL_OFFSETS, Syscall::Accept)
+}
+
+#[sockwho_tracepoint]
+fn sys_enter_getsockname(ctx: TracePointContext) -> HandlerResult {
+    syscall_enter_deferred(ctx, &CONTROL_OFFSETS, Syscall::GetSockName)
+}
+
+#[sockwho_tracepoint]
+fn sys_enter_getpeername(ctx: TracePointContext) -> HandlerResult {
+    syscall_enter_deferred(ctx, &CONTROL_OFFSETS, Syscall::GetPeerName)
+}
+
+#[sockwho_tracepoint]
+fn sys_enter_sendmsg(ctx: TracePointContext) -> HandlerResult {
+    let msghdr: *const MsgHdr = ctx.read_field(MSG_OFFSETS.msghdr)?;
+    let msghdr = unsafe { bpf_probe_read_user(msghdr) }?;
+    let address = match msghdr.name.is_null() {
+        true => PendingAddress::Peer { resolved: false },
+        false => PendingAddress::Input { sockaddr: msghdr.name, length: msghdr.name_length },
+    };
+    track_syscall(&ctx, MSG_OFFSETS.fd, Syscall::SendMsg, address)
+}
+
+#[sockwho_tracepoint]
+fn sys_enter_recvmsg(ctx: TracePointContext) -> HandlerResult {
+    let msghdr: *const MsgHdr = ctx.read_field(MSG_OFFSETS.msghdr)?;
+    let sockaddr = unsafe { bpf_probe_read_user(msghdr) }?.name;
+    let address = match sockaddr.is_null() {
+        true => PendingAddress::Peer { resolved: false },
+        // The kernel writes the length of the source address back into msghdr's msg_namelen.
+        false => PendingAddress::Output { sockaddr, length: unsafe { core::ptr::addr_of!((*msghdr).name_length) } },
+    };
+    track_syscall(&ctx, MSG_OFFSETS.fd, Syscall::RecvMsg, address)
+}
+
+#[sockwho_tracepoint]
+fn sys_enter_sendmmsg(ctx: TracePointContext) -> HandlerResult {
+    let messages = ctx.read_field(MSG_OFFSETS.msghdr)?;
+    track_syscall(&ctx, MSG_OFFSETS.fd, Syscall::SendMmsg, PendingAddress::Messages(messages))
+}
+
+#[sockwho_tracepoint]
+fn sys_enter_recvmmsg(ctx: TracePointContext) -> HandlerResult {
+    let messages = ctx.read_field(MSG_OFFSETS.msghdr)?;
+    track_syscall(&ctx, MSG_OFFSETS.fd, Syscall::RecvMmsg, PendingAddress::Messages(messages))
+}
+
+#[sockwho_tracepoint]
+fn sys_enter_socket(ctx: TracePointContext) -> HandlerResult {
+    // A new socket can't match the addressing filters yet.
+    if !process_allowed() || addressing_filtered() {
+        return Ok(());
+    }
+    let pid = bpf_get_current_pid_tgid();
+    let (uid, gid) = current_credentials();
+    let event = SocketCreatedEvent {
+        pid: as_pid(pid),
+        domain: ctx.read_field(16)?,
+        socket_type: ctx.read_field(24)?,
+        protocol: ctx.read_field(32)?,
+        tid: as_tid(pid),
+        uid,
+        gid,
+        ppid: current_parent(),
+        net_namespace: current_net_namespace(),
+        _padding: 0,
+        command: bpf_get_current_comm()?,
+        return_value: 0,
+        timestamp: now(),
+        duration: 0,
+        cgroup_id: current_cgroup(),
+        start_time: current_start_time(),
+    };
+    unsafe { PID_SOCKET.insert(&pid, &event, 0) }?;
+
+    Ok(())
+}
+
+#[sockwho_tracepoint]
+fn sys_enter_listen(ctx: TracePointContext) -> HandlerResult {
+    let backlog = ctx.read_field(24)?;
+    socket_call_enter(ctx, Syscall::Listen, backlog)
+}
+
+#[sockwho_tracepoint]
+fn sys_enter_shutdown(ctx: TracePointContext) -> HandlerResult {
+    let how = ctx.read_field(24)?;
+    socket_call_enter(ctx, Syscall::Shutdown, how)
+}
+
+#[sockwho_tracepoint]
+fn sys_enter_setsockopt(ctx: TracePointContext) -> HandlerResult {
+    let pid = bpf_get_current_pid_tgid();
+    if !socket_allowed(as_pid(pid), ctx.read_field(16)?) {
+        return Ok(());
+    }
+    let value: *const u8 = ctx.read_field(40)?;
+    let value_length: i32 = ctx.read_field(48)?;
+    let (uid, gid) = current_credentials();
+    let mut event = SocketOptionEvent {
+        pid: as_pid(pid),
+        fd: ctx.read_field(16)?,
+        level: ctx.read_field(24)?,
+        name: ctx.read_field(32)?,
+        value_length: 0,
+        value: [0; OPTION_VALUE_MAX],
+        command: bpf_get_current_comm()?,
+        tid: as_tid(pid),
+        uid,
+        gid,
+        ppid: current_parent(),
+        net_namespace: current_net_namespace(),
+        return_value: 0,
+        timestamp: now(),
+        duration: 0,
+        cgroup_id: current_cgroup(),
+        start_time: current_start_time(),
+    };
+    // Only the first bytes of the value are kept, which is enough for integers and interface names.
+    if !value.is_null() && value_length > 0 {
+        let length = (value_length as usize).min(OPTION_VALUE_MAX);
+        unsafe { bpf_probe_read_user_buf(value, &mut event.value[..length]) }?;
+        event.value_length = value_length as u32;
+    }
+    unsafe { PID_OPTION.insert(&pid, &event, 0) }?;
+
+    Ok(())
+}
+
+#[sockwho_tracepoint]
+fn sys_enter_close(ctx: TracePointContext) -> HandlerResult {
+    let pid = as_pid(bpf_get_current_pid_tgid());
+    let fd: u32 = ctx.read_field(16)?;
+    if unsafe { SOCKET_FDS.get(&socket_key(pid, fd)) }.is_none() {
+        return Ok(());
+    }
+    socket_call_enter(ctx, Syscall::Close, 0)
+}
+
 #[sockwho_tracepoint]
 fn sys_exit_bind(ctx: TracePointContext) -> HandlerResult {
     syscall_exit(ctx)
@@ -86,20 +344,172 @@ fn sys_exit_sendto(ctx: TracePointContext) -> HandlerResult {
     syscall_exit(ctx)
 }
 
+#[sockwho_tracepoint]
+fn sys_exit_accept(ctx: TracePointContext) -> HandlerResult {
+    syscall_exit(ctx)
+}
+
+#[sockwho_tracepoint]
+fn sys_exit_accept4(ctx: TracePointContext) -> HandlerResult {
+    syscall_exit(ctx)
+}
+
+#[sockwho_tracepoint]
+fn sys_exit_getsockname(ctx: TracePointContext) -> HandlerResult {
+    syscall_exit(ctx)
+}
+
+#[sockwho_tracepoint]
+fn sys_exit_getpeername(ctx: TracePointContext) -> HandlerResult {
+    syscall_exit(ctx)
+}
+
+#[sockwho_tracepoint]
+fn sys_exit_sendmsg(ctx: TracePointContext) -> HandlerResult {
+    syscall_exit(ctx)
+}
+
+#[sockwho_tracepoint]
+fn sys_exit_recvmsg(ctx: TracePointContext) -> HandlerResult {
+    syscall_exit(ctx)
+}
+
+#[sockwho_tracepoint]
+fn sys_exit_sendmmsg(ctx: TracePointContext) -> HandlerResult {
+    syscall_exit(ctx)
+}
+
+#[sockwho_tracepoint]
+fn sys_exit_recvmmsg(ctx: TracePointContext) -> HandlerResult {
+    syscall_exit(ctx)
+}
+
+#[sockwho_tracepoint]
+fn sys_exit_socket(ctx: TracePointContext) -> HandlerResult {
+    let pid = bpf_get_current_pid_tgid();
+    let event = unsafe { &mut *PID_SOCKET.get_ptr_mut(&pid).ok_or(1)? };
+    event.return_value = ctx.read_field(16)?;
+    finish_syscall(&mut event.timestamp, &mut event.duration);
+    if event.return_value >= 0 {
+        remember_socket(event.pid, event.return_value as u32);
+    }
+
+    output(&ctx, unsafe { &mut SOCKET_CREATED_EVENTS }, event);
+    unsafe { PID_SOCKET.remove(&pid)? };
+
+    Ok(())
+}
+
+#[sockwho_tracepoint]
+fn sys_exit_listen(ctx: TracePointContext) -> HandlerResult {
+    socket_call_exit(ctx)
+}
+
+#[sockwho_tracepoint]
+fn sys_exit_shutdown(ctx: TracePointContext) -> HandlerResult {
+    socket_call_exit(ctx)
+}
+
+#[sockwho_tracepoint]
+fn sys_exit_close(ctx: TracePointContext) -> HandlerResult {
+    socket_call_exit(ctx)
+}
+
+#[sockwho_tracepoint]
+fn sys_exit_setsockopt(ctx: TracePointContext) -> HandlerResult {
+    let pid = bpf_get_current_pid_tgid();
+    let event = unsafe { &mut *PID_OPTION.get_ptr_mut(&pid).ok_or(1)? };
+    event.return_value = ctx.read_field(16)?;
+    finish_syscall(&mut event.timestamp, &mut event.duration);
+    if event.return_value == 0 {
+        remember_socket(event.pid, event.fd);
+    }
+
+    output(&ctx, unsafe { &mut SOCKET_OPTION_EVENTS }, event);
+    unsafe { PID_OPTION.remove(&pid)? };
+
+    Ok(())
+}
+
+#[sockwho_tracepoint]
+fn sched_process_exec(ctx: TracePointContext) -> HandlerResult {
+    let event = unsafe { &mut *EXEC_EVENT.get_ptr_mut(0).ok_or(1)? };
+    // The filename is a __data_loc field, whose lower 16 bits are the offset of the string within the tracepoint's data.
+    let location: u32 = ctx.read_field(8)?;
+    let filename = (ctx.as_ptr() as *const u8).wrapping_add((location & 0xffff) as usize);
+    let path = unsafe { bpf_probe_read_kernel_str_bytes(filename, &mut event.path) }?;
+    event.path_length = path.len() as u32;
+    event.pid = as_pid(bpf_get_current_pid_tgid());
+    event.timestamp = now();
+    event.cgroup_id = current_cgroup();
+    event.start_time = current_start_time();
+    event.command = bpf_get_current_comm()?;
+
+    output(&ctx, unsafe { &mut PROCESS_EXEC_EVENTS }, event);
+
+    Ok(())
+}
+
+// Where the fields of the task_newtask tracepoint are. This is set by userspace when loading the programs, since that
+// tracepoint's layout isn't stable.
+#[no_mangle]
+static NEW_TASK_OFFSETS: NewTaskOffsets = NewTaskOffsets { pid: 0, clone_flags: 0 };
+
+// A new thread is part of a process that's already in the pid filter if it's traced at all.
+const CLONE_THREAD: u64 = 0x10000;
+
+#[sockwho_tracepoint]
+fn task_newtask(ctx: TracePointContext) -> HandlerResult {
+    // This runs in the context of the parent, before the child can run.
+    let offsets = unsafe { read_volatile(&NEW_TASK_OFFSETS) };
+    let clone_flags: u64 = ctx.read_field(offsets.clone_flags as usize)?;
+    if clone_flags & CLONE_THREAD != 0 {
+        return Ok(());
+    }
+    let child_pid: u32 = ctx.read_field(offsets.pid as usize)?;
+    follow_child(child_pid);
+
+    Ok(())
+}
+
+#[sockwho_tracepoint]
+fn sched_process_exit(_ctx: TracePointContext) -> HandlerResult {
+    // This runs for every thread that exits, but only processes are in the pid filter.
+    let pid = bpf_get_current_pid_tgid();
+    if as_tid(pid) == as_pid(pid) {
+        forget_process(as_pid(pid));
+    }
+
+    Ok(())
+}
+
 #[sockwho_tracepoint]
 fn inet_sock_set_state(ctx: TracePointContext) -> HandlerResult {
+    if !process_allowed() {
+        return Ok(());
+    }
     let pid = bpf_get_current_pid_tgid();
     let old_state = ctx.read_field(16)?;
     let new_state = ctx.read_field(20)?;
     let src_port = ctx.read_field(24)?;
     let dst_port = ctx.read_field(26)?;
-    let family = match ctx.read_field::<u16>(28)? {
-        AF_INET => AddressFamily::Ipv4,
-        AF_INET6 => AddressFamily::Ipv6,
-        _ => return Err(1.into()),
-    };
+    let family = parse_family(ctx.read_field(28)?)?;
     let (src_address, dst_address) = read_address_pair(&family, &ctx)?;
+    // Unlike in sockaddrs, ports are in host byte order here.
+    if addressing_filtered()
+        && !endpoint_allowed(&family, &src_address, u16::to_be(src_port))
+        && !endpoint_allowed(&family, &dst_address, u16::to_be(dst_port))
+    {
+        return Ok(());
+    }
     let command = bpf_get_current_comm()?;
+    let (uid, gid) = current_credentials();
+    // The current process may be unrelated to the socket, so its namespace is only used if there's a single one.
+    let sock: u64 = ctx.read_field(8)?;
+    let net_namespace = match socket_net_namespace(sock as *const u8) {
+        0 => current_net_namespace(),
+        net_namespace => net_namespace,
+    };
 
     let event = SocketStateEvent {
         src_port,
@@ -112,81 +522,349 @@ fn inet_sock_set_state(ctx: TracePointContext) -> HandlerResult {
         pid: as_pid(pid),
         command,
         _padding: 0,
+        tid: as_tid(pid),
+        uid,
+        gid,
+        ppid: current_parent(),
+        net_namespace,
+        timestamp: now(),
+        cgroup_id: current_cgroup(),
+        start_time: current_start_time(),
     };
-    unsafe { SOCKET_STATE_EVENTS.output(&ctx, &event, 0) };
+    output(&ctx, unsafe { &mut SOCKET_STATE_EVENTS }, &event);
 
     Ok(())
 }
 
-fn syscall_enter(ctx: TracePointContext, offsets: &ArgumentOffsets, syscall: Syscall) -> HandlerResult {
+/// Handles the entry of a syscall that takes a socket and at most a single integer argument, e.g. listen(2).
+fn socket_call_enter(ctx: TracePointContext, syscall: Syscall, argument: i64) -> HandlerResult {
     let pid = bpf_get_current_pid_tgid();
-    let fd: i32 = ctx.read_field(offsets.fd)?;
-    if fd == -1 {
+    let fd: i32 = ctx.read_field(16)?;
+    if fd == -1 || !socket_allowed(as_pid(pid), fd as u32) {
         return Ok(());
     }
-    let sockaddr: *const u8 = ctx.read_field(offsets.sockaddr)?;
-    let family: u16 = unsafe { bpf_probe_read_user(sockaddr as *const u16) }?;
-    let family = match family {
-        AF_INET => AddressFamily::Ipv4,
-        AF_INET6 => AddressFamily::Ipv6,
-        _ => return Err(1.into()),
+    let (uid, gid) = current_credentials();
+    let event = SocketCallEvent {
+        pid: as_pid(pid),
+        fd: fd as u32,
+        syscall,
+        _padding: [0; 3],
+        tid: as_tid(pid),
+        uid,
+        gid,
+        ppid: current_parent(),
+        net_namespace: current_net_namespace(),
+        argument,
+        command: bpf_get_current_comm()?,
+        return_value: 0,
+        timestamp: now(),
+        duration: 0,
+        cgroup_id: current_cgroup(),
+        start_time: current_start_time(),
     };
-    let (address, port) = read_sockaddr(&family, sockaddr)?;
+    unsafe { PID_CALL.insert(&pid, &event, 0) }?;
+
+    Ok(())
+}
+
+fn socket_call_exit(ctx: TracePointContext) -> HandlerResult {
+    let pid = bpf_get_current_pid_tgid();
+    let event = unsafe { &mut *PID_CALL.get_ptr_mut(&pid).ok_or(1)? };
+    event.return_value = ctx.read_field(16)?;
+    finish_syscall(&mut event.timestamp, &mut event.duration);
+    match event.syscall {
+        // The file descriptor is released even if close(2) fails.
+        Syscall::Close => forget_socket(event.pid, event.fd),
+        _ if event.return_value >= 0 => remember_socket(event.pid, event.fd),
+        _ => (),
+    };
+
+    output(&ctx, unsafe { &mut SOCKET_CALL_EVENTS }, event);
+    unsafe { PID_CALL.remove(&pid)? };
+
+    Ok(())
+}
+
+fn syscall_enter(ctx: TracePointContext, offsets: &ArgumentOffsets, syscall: Syscall) -> HandlerResult {
+    let sockaddr = ctx.read_field(offsets.sockaddr)?;
+    let length = ctx.read_field(offsets.sockaddr_length)?;
+    track_syscall(&ctx, offsets.fd, syscall, PendingAddress::Input { sockaddr, length })
+}
+
+/// Handles the entry of a syscall whose sockaddr is an output parameter, e.g. accept(2).
+fn syscall_enter_deferred(ctx: TracePointContext, offsets: &ArgumentOffsets, syscall: Syscall) -> HandlerResult {
+    let sockaddr = ctx.read_field(offsets.sockaddr)?;
+    let length = ctx.read_field(offsets.sockaddr_length)?;
+    track_syscall(&ctx, offsets.fd, syscall, PendingAddress::Output { sockaddr, length })
+}
+
+/// Handles the entry of a syscall on a connected socket that was given no sockaddr, e.g. sendto(2) with a NULL
+/// destination.
+fn syscall_enter_connected(ctx: TracePointContext, offsets: &ArgumentOffsets, syscall: Syscall) -> HandlerResult {
+    track_syscall(&ctx, offsets.fd, syscall, PendingAddress::Peer { resolved: false })
+}
+
+fn track_syscall(
+    ctx: &TracePointContext,
+    fd_offset: usize,
+    syscall: Syscall,
+    address: PendingAddress,
+) -> HandlerResult {
+    let pid = bpf_get_current_pid_tgid();
+    let fd: i32 = ctx.read_field(fd_offset)?;
+    if fd == -1 || !process_allowed() {
+        return Ok(());
+    }
     let command = bpf_get_current_comm()?;
+    let (uid, gid) = current_credentials();
 
-    let event = SockaddrEvent {
+    let mut event = SockaddrEvent {
         pid: as_pid(pid),
         fd: fd as u32,
-        address,
-        port,
-        family,
+        address: [0; 16],
+        port: 0,
+        family: AddressFamily::Unspecified,
         syscall,
+        local_port: 0,
+        implicit_peer: false,
+        path_length: 0,
+        local_address: [0; 16],
         return_value: 0,
-        _padding: 0,
         command,
+        timestamp: now(),
+        duration: 0,
+        cgroup_id: current_cgroup(),
+        start_time: current_start_time(),
+        path: [0; UNIX_PATH_MAX],
+        tid: as_tid(pid),
+        uid,
+        gid,
+        ppid: current_parent(),
+        net_namespace: current_net_namespace(),
     };
-    unsafe { PID_EVENT.insert(&pid, &event, 0) }?;
+    if let PendingAddress::Input { sockaddr, length } = address {
+        decode_sockaddr(&mut event, sockaddr, length)?;
+        if !addressing_allowed(&event) {
+            return Ok(());
+        }
+    }
+    let pending = PendingEvent { event, address };
+    unsafe { PID_EVENT.insert(&pid, &pending, 0) }?;
 
     Ok(())
 }
 
 fn syscall_exit(ctx: TracePointContext) -> HandlerResult {
     let pid = bpf_get_current_pid_tgid();
-    let mut event = unsafe { &mut *PID_EVENT.get_ptr_mut(&pid).ok_or(1)? };
+    let pending = unsafe { &mut *PID_EVENT.get_ptr_mut(&pid).ok_or(1)? };
+    let result = finish_event(&ctx, pending);
+    unsafe { PID_EVENT.remove(&pid)? };
+    result
+}
+
+fn finish_event(ctx: &TracePointContext, pending: &mut PendingEvent) -> HandlerResult {
+    let event = &mut pending.event;
     event.return_value = ctx.read_field(16)?;
     event.command = bpf_get_current_comm()?;
+    finish_syscall(&mut event.timestamp, &mut event.duration);
+    if event.return_value >= 0 {
+        remember_socket(event.pid, event.fd);
+        if let Syscall::Accept = event.syscall {
+            remember_socket(event.pid, event.return_value as u32);
+        }
+    }
+    match pending.address {
+        PendingAddress::Input { .. } => (),
+        // The kernel only fills in the sockaddr on success. Otherwise, or if none was given, the event is sent without
+        // an address.
+        PendingAddress::Output { sockaddr, length } if event.return_value >= 0 && !sockaddr.is_null() => {
+            // The sockaddr can also be left empty, e.g. by recvfrom(2) on a TCP socket.
+            let length = unsafe { bpf_probe_read_user(length) }.unwrap_or_default();
+            if decode_sockaddr(event, sockaddr, length).is_err() {
+                event.family = AddressFamily::Unspecified;
+            }
+        }
+        PendingAddress::Output { .. } => (),
+        PendingAddress::Messages(messages) => return output_messages(ctx, event, messages),
+        // If this wasn't an inet socket the peer couldn't be resolved, so the event is sent without an address.
+        PendingAddress::Peer { .. } => (),
+    };
+    if !addressing_allowed(event) {
+        return Ok(());
+    }
+    if event.return_value >= 0 {
+        mark_matched(event);
+    }
+    if summarize(event) {
+        return Ok(());
+    }
 
-    unsafe { SOCKADDR_EVENTS.output(&ctx, &event, 0) };
-    unsafe { PID_EVENT.remove(&pid)? };
+    output(ctx, unsafe { &mut SOCKADDR_EVENTS }, event);
 
     Ok(())
 }
 
+/// Emits an event for every message processed by a sendmmsg/recvmmsg call. A call that failed, or that processed no
+/// messages, is sent as a single event without an address.
+fn output_messages(ctx: &TracePointContext, event: &mut SockaddrEvent, messages: *const MmsgHdr) -> HandlerResult {
+    // On success, the return value is the number of messages that were processed.
+    let count = event.return_value;
+    if count <= 0 {
+        output_message(ctx, event);
+        return Ok(());
+    }
+    for index in 0..MAX_MMSG_ENTRIES {
+        if index as i64 >= count {
+            break;
+        }
+        let message = unsafe { bpf_probe_read_user(messages.wrapping_add(index)) }?;
+        // Messages without a name were sent to or received from the peer of a connected socket, which isn't resolved
+        // for every message so they're sent without an address.
+        event.family = AddressFamily::Unspecified;
+        (event.address, event.port, event.path_length) = ([0; 16], 0, 0);
+        let sockaddr = message.header.name;
+        if !sockaddr.is_null() && decode_sockaddr(event, sockaddr, message.header.name_length).is_err() {
+            event.family = AddressFamily::Unspecified;
+        }
+        output_message(ctx, event);
+    }
+    Ok(())
+}
+
+/// Emits the event for one of the messages of a sendmmsg/recvmmsg call, if it passes the addressing filters.
+fn output_message(ctx: &TracePointContext, event: &mut SockaddrEvent) {
+    if !addressing_allowed(event) {
+        return;
+    }
+    if event.return_value >= 0 {
+        mark_matched(event);
+    }
+    if !summarize(event) {
+        output(ctx, unsafe { &mut SOCKADDR_EVENTS }, event);
+    }
+}
+
+/// Decodes a sockaddr, making sure its length covers the whole address.
+///
+/// e.g. recvfrom(2) on a TCP socket sets the length to 0 and leaves the sockaddr untouched.
+fn decode_sockaddr(event: &mut SockaddrEvent, sockaddr: *const u8, length: i32) -> Result<(), i64> {
+    if length < size_of::<u16>() as i32 {
+        return Err(1);
+    }
+    let family = read_family(sockaddr)?;
+    if (length as usize) < sockaddr_size(&family) {
+        return Err(1);
+    }
+    event.family = family;
+    match event.family {
+        AddressFamily::Ipv4 | AddressFamily::Ipv6 => {
+            (event.address, event.port) = read_sockaddr(&event.family, sockaddr)?;
+        }
+        AddressFamily::Unix => read_unix_path(event, sockaddr, length as usize)?,
+        AddressFamily::Unspecified => return Err(1),
+    };
+    Ok(())
+}
+
+/// Reads the path in a sockaddr_un. Abstract socket names start with a NUL byte and, unlike regular paths, they're
+/// not NUL terminated so the length is what determines where they end.
+fn read_unix_path(event: &mut SockaddrEvent, sockaddr: *const u8, length: usize) -> Result<(), i64> {
+    let path_length = (length - size_of::<u16>()).min(UNIX_PATH_MAX);
+    let path = sockaddr.wrapping_add(size_of::<u16>());
+    unsafe { bpf_probe_read_user_buf(path, &mut event.path[..path_length]) }?;
+    event.path_length = path_length as u8;
+    Ok(())
+}
+
+fn socket_key(pid: u32, fd: u32) -> u64 {
+    ((pid as u64) << 32) | fd as u64
+}
+
+fn remember_socket(pid: u32, fd: u32) {
+    // This is best effort: at worst a close(2) call on this socket won't be traced. Sockets that are already known are
+    // left untouched so they stay marked as matching the addressing filters.
+    let _ = unsafe { SOCKET_FDS.insert(&socket_key(pid, fd), &0, BPF_NOEXIST) };
+}
+
+/// Marks the socket behind an event that passed the addressing filters, so the events on it that don't involve an
+/// address (e.g. close(2)) pass them too.
+fn mark_matched(event: &SockaddrEvent) {
+    if !addressing_filtered() {
+        return;
+    }
+    let _ = unsafe { SOCKET_FDS.insert(&socket_key(event.pid, event.fd), &1, 0) };
+    if let Syscall::Accept = event.syscall {
+        let _ = unsafe { SOCKET_FDS.insert(&socket_key(event.pid, event.return_value as u32), &1, 0) };
+    }
+}
+
+fn socket_matched(pid: u32, fd: u32) -> bool {
+    unsafe { SOCKET_FDS.get(&socket_key(pid, fd)) }.is_some_and(|matched| *matched == 1)
+}
+
+/// Whether the events on a socket that don't involve an address should be sent to userspace.
+fn socket_allowed(pid: u32, fd: u32) -> bool {
+    process_allowed() && (!addressing_filtered() || socket_matched(pid, fd))
+}
+
+/// Whether an event passes the addressing filters, either because of the addresses it involves or because its socket
+/// already did.
+fn addressing_allowed(event: &SockaddrEvent) -> bool {
+    !addressing_filtered()
+        || endpoint_allowed(&event.family, &event.address, event.port)
+        || (event.implicit_peer && endpoint_allowed(&event.family, &event.local_address, event.local_port))
+        || socket_matched(event.pid, event.fd)
+}
+
+fn forget_socket(pid: u32, fd: u32) {
+    let _ = unsafe { SOCKET_FDS.remove(&socket_key(pid, fd)) };
+}
+
+pub(crate) fn parse_family(family: u16) -> Result<AddressFamily, i64> {
+    match family {
+        AF_INET => Ok(AddressFamily::Ipv4),
+        AF_INET6 => Ok(AddressFamily::Ipv6),
+        AF_UNIX => Ok(AddressFamily::Unix),
+        _ => Err(1),
+    }
+}
+
+fn read_family(sockaddr: *const u8) -> Result<AddressFamily, i64> {
+    let family: u16 = unsafe { bpf_probe_read_user(sockaddr as *const u16) }?;
+    parse_family(family)
+}
+
+fn sockaddr_size(family: &AddressFamily) -> usize {
+    match family {
+        AddressFamily::Ipv4 => size_of::<SockaddrIn>(),
+        AddressFamily::Ipv6 => size_of::<SockaddrIn6>(),
+        // Unnamed sockets only contain the family.
+        AddressFamily::Unix | AddressFamily::Unspecified => size_of::<u16>(),
+    }
+}
+
 fn read_sockaddr(family: &AddressFamily, sockaddr: *const u8) -> Result<([u8; 16], u16), i64> {
     match family {
         AddressFamily::Ipv4 => {
             let sockaddr = unsafe { bpf_probe_read_user(sockaddr as *const SockaddrIn) }?;
-            let a = &sockaddr.address;
-            let address = [a[0], a[1], a[2], a[3], 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
-            Ok((address, sockaddr.port))
+            Ok((ipv4_address(sockaddr.address), sockaddr.port))
         }
         AddressFamily::Ipv6 => {
             let sockaddr = unsafe { bpf_probe_read_user(sockaddr as *const SockaddrIn6) }?;
             Ok((sockaddr.address, sockaddr.port))
         }
+        AddressFamily::Unix | AddressFamily::Unspecified => Err(1),
     }
 }
 
 fn read_address_pair(family: &AddressFamily, ctx: &TracePointContext) -> Result<([u8; 16], [u8; 16]), u32> {
     match family {
         AddressFamily::Ipv4 => {
-            let s: [u8; 4] = ctx.read_field(32)?;
-            let d: [u8; 4] = ctx.read_field(36)?;
-            Ok((
-                [s[0], s[1], s[2], s[3], 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
-                [d[0], d[1], d[2], d[3], 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
-            ))
+            let s = ctx.read_field(32)?;
+            let d = ctx.read_field(36)?;
+            Ok((ipv4_address(s), ipv4_address(d)))
         }
         AddressFamily::Ipv6 => Ok((ctx.read_field(40)?, ctx.read_field(56)?)),
+        AddressFamily::Unix | AddressFamily::Unspecified => Err(1),
     }
 }
@@ -1,4 +1,131 @@
+use aya_bpf::helpers::{
+    bpf_get_current_cgroup_id, bpf_get_current_task, bpf_get_current_uid_gid, bpf_ktime_get_ns, bpf_probe_read_kernel,
+};
+use core::ptr::read_volatile;
+use sockwho_common::KernelOffsets;
+
+// Where the fields read from the kernel's structs are. This is set by userspace when loading the programs, and left
+// zeroed if it couldn't be found, in which case the kprobes aren't attached.
+#[no_mangle]
+static KERNEL_OFFSETS: KernelOffsets = KernelOffsets {
+    socket_sk: 0,
+    sock_daddr: 0,
+    sock_rcv_saddr: 0,
+    sock_dport: 0,
+    sock_num: 0,
+    sock_family: 0,
+    sock_v6_daddr: 0,
+    sock_v6_rcv_saddr: 0,
+    sock_net: 0,
+    task_real_parent: 0,
+    task_tgid: 0,
+    task_group_leader: 0,
+    task_start_time: 0,
+    task_nsproxy: 0,
+    nsproxy_net_ns: 0,
+    net_inum: 0,
+};
+
+/// Where the fields read from the kernel's structs are.
+pub fn kernel_offsets() -> KernelOffsets {
+    unsafe { read_volatile(&KERNEL_OFFSETS) }
+}
+
+/// Reads a field of a kernel struct.
+pub fn read_kernel<T>(base: *const u8, offset: u32) -> Result<T, i64> {
+    unsafe { bpf_probe_read_kernel(base.wrapping_add(offset as usize) as *const T) }
+}
+
 /// Converts a PID+TGID into what userspace considers a PID.
 pub fn as_pid(pid_tgid: u64) -> u32 {
     (pid_tgid >> 32) as u32
 }
+
+/// Converts a PID+TGID into what userspace considers a thread id.
+pub fn as_tid(pid_tgid: u64) -> u32 {
+    pid_tgid as u32
+}
+
+/// The user and group ids of the current process.
+pub fn current_credentials() -> (u32, u32) {
+    let uid_gid = bpf_get_current_uid_gid();
+    (uid_gid as u32, (uid_gid >> 32) as u32)
+}
+
+/// The pid of the current process' parent, or 0 if the offsets needed to find it aren't known.
+pub fn current_parent() -> u32 {
+    let offsets = kernel_offsets();
+    if offsets.task_real_parent == 0 {
+        return 0;
+    }
+    let task = unsafe { bpf_get_current_task() } as *const u8;
+    let Ok(parent) = read_kernel::<*const u8>(task, offsets.task_real_parent) else {
+        return 0;
+    };
+    read_kernel(parent, offsets.task_tgid).unwrap_or_default()
+}
+
+/// When the current process started, in nanoseconds since boot, or 0 if the offsets needed to find it aren't known.
+pub fn current_start_time() -> u64 {
+    let offsets = kernel_offsets();
+    if offsets.task_group_leader == 0 {
+        return 0;
+    }
+    // Every thread has its own start time, the process' is the one of its main thread.
+    let task = unsafe { bpf_get_current_task() } as *const u8;
+    let Ok(leader) = read_kernel::<*const u8>(task, offsets.task_group_leader) else {
+        return 0;
+    };
+    read_kernel(leader, offsets.task_start_time).unwrap_or_default()
+}
+
+/// The inode of the network namespace the current process is in, or 0 if the offsets needed to find it aren't known.
+pub fn current_net_namespace() -> u32 {
+    let offsets = kernel_offsets();
+    if offsets.task_nsproxy == 0 {
+        return 0;
+    }
+    let task = unsafe { bpf_get_current_task() } as *const u8;
+    // The namespaces are already gone for processes that are exiting, in which case this fails.
+    let Ok(nsproxy) = read_kernel::<*const u8>(task, offsets.task_nsproxy) else {
+        return 0;
+    };
+    let Ok(net) = read_kernel::<*const u8>(nsproxy, offsets.nsproxy_net_ns) else {
+        return 0;
+    };
+    read_kernel(net, offsets.net_inum).unwrap_or_default()
+}
+
+/// The inode of the network namespace a `struct sock` is in, or 0 if the offsets needed to find it aren't known.
+pub fn socket_net_namespace(sock: *const u8) -> u32 {
+    let offsets = kernel_offsets();
+    if offsets.sock_net == 0 {
+        return 0;
+    }
+    let Ok(net) = read_kernel::<*const u8>(sock, offsets.sock_net) else {
+        return 0;
+    };
+    read_kernel(net, offsets.net_inum).unwrap_or_default()
+}
+
+/// The id of the cgroup the current process belongs to, which is the inode of its directory in the cgroup v2 hierarchy.
+pub fn current_cgroup() -> u64 {
+    unsafe { bpf_get_current_cgroup_id() }
+}
+
+/// Stores an IPv4 address in the 16 byte buffer used for addresses in events.
+pub fn ipv4_address(a: [u8; 4]) -> [u8; 16] {
+    [a[0], a[1], a[2], a[3], 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+}
+
+/// The current time, in nanoseconds since boot.
+pub fn now() -> u64 {
+    unsafe { bpf_ktime_get_ns() }
+}
+
+/// Marks a syscall as finished by turning the time it was entered into the time it returned and how long it took.
+pub fn finish_syscall(timestamp: &mut u64, duration: &mut u64) {
+    let exit = now();
+    *duration = exit - *timestamp;
+    *timestamp = exit;
+}
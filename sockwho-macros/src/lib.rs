@@ -24,6 +24,14 @@ fn uretprobe_decorator() -> Decorator {
     }
 }
 
+fn kprobe_decorator() -> Decorator {
+    Decorator {
+        macro_name: quote! { aya_bpf::macros::kprobe },
+        context_name: quote! { aya_bpf::programs::ProbeContext },
+        probe_type: "kprobe",
+    }
+}
+
 fn tracepoint_decorator() -> Decorator {
     Decorator {
         macro_name: quote! { aya_bpf::macros::tracepoint },
@@ -42,6 +50,11 @@ pub fn sockwho_uretprobe(_args: TokenStream, item: TokenStream) -> TokenStream {
     decorate_item(uretprobe_decorator(), item)
 }
 
+#[proc_macro_attribute]
+pub fn sockwho_kprobe(_args: TokenStream, item: TokenStream) -> TokenStream {
+    decorate_item(kprobe_decorator(), item)
+}
+
 #[proc_macro_attribute]
 pub fn sockwho_tracepoint(_args: TokenStream, item: TokenStream) -> TokenStream {
     decorate_item(tracepoint_decorator(), item)
@@ -1,11 +1,15 @@
 use anyhow::{anyhow, Error};
-use aya::{programs::TracePoint, Bpf};
+use aya::{
+    programs::{KProbe, TracePoint},
+    Bpf,
+};
 use log::info;
 
 /// Attaches probes.
 pub struct ProbeAttacher<'a> {
     bpf: &'a mut Bpf,
     tracepoints: Vec<Tracepoint>,
+    kprobes: Vec<String>,
 }
 
 impl<'a> ProbeAttacher<'a> {
@@ -21,6 +25,17 @@ impl<'a> ProbeAttacher<'a> {
         }
         Ok(())
     }
+
+    pub fn attach_kprobes(&mut self) -> Result<(), Error> {
+        for symbol in &self.kprobes {
+            info!("Attaching kprobe '{symbol}'");
+            let program: &mut KProbe =
+                self.bpf.program_mut(symbol).ok_or_else(|| anyhow!("program '{symbol}' not found"))?.try_into()?;
+            program.load()?;
+            program.attach(symbol, 0)?;
+        }
+        Ok(())
+    }
 }
 
 pub struct ProbeAttacherBuilder<'a> {
@@ -30,7 +45,7 @@ pub struct ProbeAttacherBuilder<'a> {
 impl<'a> ProbeAttacherBuilder<'a> {
     /// Construct a new builder for the given BPF instance.
     pub fn new(bpf: &'a mut Bpf) -> Self {
-        let attacher = ProbeAttacher { bpf, tracepoints: Vec::new() };
+        let attacher = ProbeAttacher { bpf, tracepoints: Vec::new(), kprobes: Vec::new() };
         Self { attacher }
     }
 
@@ -40,6 +55,12 @@ impl<'a> ProbeAttacherBuilder<'a> {
         self
     }
 
+    /// Adds a kprobe to be attached to the kernel function with the same name.
+    pub fn with_kprobe<S: Into<String>>(mut self, symbol: S) -> Self {
+        self.attacher.kprobes.push(symbol.into());
+        self
+    }
+
     /// Builds the probe attacher.
     pub fn build(self) -> ProbeAttacher<'a> {
         self.attacher
@@ -49,6 +70,8 @@ impl<'a> ProbeAttacherBuilder<'a> {
 pub enum Tracepoint {
     Syscall(String),
     Socket(String),
+    Sched(String),
+    Task(String),
 }
 
 impl Tracepoint {
@@ -60,10 +83,20 @@ impl Tracepoint {
         Self::Socket(name.into())
     }
 
+    pub fn sched<S: Into<String>>(name: S) -> Self {
+        Self::Sched(name.into())
+    }
+
+    pub fn task<S: Into<String>>(name: S) -> Self {
+        Self::Task(name.into())
+    }
+
     fn category(&self) -> &'static str {
         match self {
             Self::Syscall(_) => "syscalls",
             Self::Socket(_) => "sock",
+            Self::Sched(_) => "sched",
+            Self::Task(_) => "task",
         }
     }
 
@@ -72,7 +105,7 @@ impl Tracepoint {
             Self::Syscall(name) => {
                 vec![format!("sys_enter_{name}"), format!("sys_exit_{name}")]
             }
-            Self::Socket(name) => vec![name.clone()],
+            Self::Socket(name) | Self::Sched(name) | Self::Task(name) => vec![name.clone()],
         }
     }
 }
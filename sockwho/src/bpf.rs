@@ -1,4 +1,6 @@
-use sockwho_common::{SockaddrEvent, SocketStateEvent};
+use sockwho_common::{
+    ProcessExecEvent, SockaddrEvent, SocketCallEvent, SocketCreatedEvent, SocketOptionEvent, SocketStateEvent,
+};
 
 /// An event generated by our eBPF probes.
 #[derive(Clone, Debug)]
@@ -8,6 +10,56 @@ pub enum BpfEvent {
 
     /// An event that changes the state of a socket.
     SocketState(SocketStateEvent),
+
+    /// An event generated when a socket is created via socket(2).
+    SocketCreated(SocketCreatedEvent),
+
+    /// An event for a syscall that takes a socket but no address. e.g. a call to listen(2).
+    SocketCall(SocketCallEvent),
+
+    /// An event generated when a socket option is set via setsockopt(2).
+    SocketOption(SocketOptionEvent),
+
+    /// An event generated when a process executes a program.
+    ProcessExec(ProcessExecEvent),
+}
+
+impl BpfEvent {
+    /// The time at which this event happened, in nanoseconds since boot.
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            Self::Sockaddr(event) => event.timestamp,
+            Self::SocketState(event) => event.timestamp,
+            Self::SocketCreated(event) => event.timestamp,
+            Self::SocketCall(event) => event.timestamp,
+            Self::SocketOption(event) => event.timestamp,
+            Self::ProcessExec(event) => event.timestamp,
+        }
+    }
+
+    /// The process that generated this event.
+    pub fn pid(&self) -> u32 {
+        match self {
+            Self::Sockaddr(event) => event.pid,
+            Self::SocketState(event) => event.pid,
+            Self::SocketCreated(event) => event.pid,
+            Self::SocketCall(event) => event.pid,
+            Self::SocketOption(event) => event.pid,
+            Self::ProcessExec(event) => event.pid,
+        }
+    }
+
+    /// The cgroup the process that generated this event was in.
+    pub fn cgroup_id(&self) -> u64 {
+        match self {
+            Self::Sockaddr(event) => event.cgroup_id,
+            Self::SocketState(event) => event.cgroup_id,
+            Self::SocketCreated(event) => event.cgroup_id,
+            Self::SocketCall(event) => event.cgroup_id,
+            Self::SocketOption(event) => event.cgroup_id,
+            Self::ProcessExec(event) => event.cgroup_id,
+        }
+    }
 }
 
 impl From<SockaddrEvent> for BpfEvent {
@@ -21,3 +73,27 @@ impl From<SocketStateEvent> for BpfEvent {
         Self::SocketState(event)
     }
 }
+
+impl From<SocketCreatedEvent> for BpfEvent {
+    fn from(event: SocketCreatedEvent) -> Self {
+        Self::SocketCreated(event)
+    }
+}
+
+impl From<SocketCallEvent> for BpfEvent {
+    fn from(event: SocketCallEvent) -> Self {
+        Self::SocketCall(event)
+    }
+}
+
+impl From<SocketOptionEvent> for BpfEvent {
+    fn from(event: SocketOptionEvent) -> Self {
+        Self::SocketOption(event)
+    }
+}
+
+impl From<ProcessExecEvent> for BpfEvent {
+    fn from(event: ProcessExecEvent) -> Self {
+        Self::ProcessExec(event)
+    }
+}
@@ -0,0 +1,316 @@
+use anyhow::{anyhow, bail, Context};
+use sockwho_common::KernelOffsets;
+use std::{borrow::Cow, fs};
+
+const BTF_MAGIC: u16 = 0xeb9f;
+const BTF_HEADER_SIZE: usize = 24;
+
+// The kinds of types, as stored in bits 24-28 of their info field.
+const BTF_KIND_INT: u32 = 1;
+const BTF_KIND_ARRAY: u32 = 3;
+const BTF_KIND_STRUCT: u32 = 4;
+const BTF_KIND_UNION: u32 = 5;
+const BTF_KIND_ENUM: u32 = 6;
+const BTF_KIND_TYPEDEF: u32 = 8;
+const BTF_KIND_VOLATILE: u32 = 9;
+const BTF_KIND_CONST: u32 = 10;
+const BTF_KIND_RESTRICT: u32 = 11;
+const BTF_KIND_FUNC_PROTO: u32 = 13;
+const BTF_KIND_VAR: u32 = 14;
+const BTF_KIND_DATASEC: u32 = 15;
+const BTF_KIND_DECL_TAG: u32 = 17;
+const BTF_KIND_TYPE_TAG: u32 = 18;
+const BTF_KIND_ENUM64: u32 = 19;
+
+/// The types described by the kernel's BTF, which is used to find where the fields the eBPF programs read are in the
+/// kernel's structs given that changes with the kernel's version and configuration.
+///
+/// aya doesn't expose the types in the BTF it parses so only what's needed to find struct members is decoded here.
+pub struct Btf {
+    // Indexed by type id. The first one is void.
+    types: Vec<BtfType>,
+    strings: Vec<u8>,
+}
+
+#[derive(Default)]
+struct BtfType {
+    name: u32,
+    kind: u32,
+    // The type this one refers to, for typedefs and qualifiers.
+    referenced: u32,
+    members: Vec<BtfMember>,
+}
+
+struct BtfMember {
+    name: u32,
+    type_id: u32,
+    // In bits from the start of the struct.
+    offset: u32,
+}
+
+impl Btf {
+    /// Reads the BTF of the running kernel, which is only available if it was built with `CONFIG_DEBUG_INFO_BTF`.
+    pub fn from_sys_fs() -> anyhow::Result<Self> {
+        let data = fs::read("/sys/kernel/btf/vmlinux").context("reading the kernel's BTF")?;
+        Self::parse(&data).context("parsing the kernel's BTF")
+    }
+
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let mut header = Reader { data, position: 0 };
+        if u16::from_ne_bytes(header.read()?) != BTF_MAGIC {
+            bail!("invalid magic number");
+        }
+        header.position = 4;
+        let header_length = header.u32()? as usize;
+        let (type_offset, type_length) = (header.u32()? as usize, header.u32()? as usize);
+        let (string_offset, string_length) = (header.u32()? as usize, header.u32()? as usize);
+        if header_length < BTF_HEADER_SIZE {
+            bail!("header is too short");
+        }
+        let section = |offset: usize, length: usize| {
+            let start = header_length + offset;
+            data.get(start..start + length).ok_or_else(|| anyhow!("section is out of bounds"))
+        };
+        let strings = section(string_offset, string_length)?.to_vec();
+        let mut reader = Reader { data: section(type_offset, type_length)?, position: 0 };
+        let mut types = vec![BtfType::default()];
+        while reader.position < reader.data.len() {
+            types.push(reader.btf_type()?);
+        }
+        Ok(Self { types, strings })
+    }
+
+    /// The offset of a member of a struct, in bytes. Members of nested structs are found by giving the path to them,
+    /// e.g. `["ns", "inum"]`, and the members of anonymous structs and unions are found as if they were the struct's.
+    pub fn member_offset(&self, struct_name: &str, path: &[&str]) -> anyhow::Result<u32> {
+        let mut type_id = self
+            .types
+            .iter()
+            // Forward declarations have no members.
+            .position(|ty| ty.kind == BTF_KIND_STRUCT && !ty.members.is_empty() && self.name(ty.name) == struct_name)
+            .ok_or_else(|| anyhow!("struct {struct_name} not found"))? as u32;
+        let mut offset = 0;
+        for name in path {
+            let (member_offset, member_type) =
+                self.find_member(type_id, name).ok_or_else(|| anyhow!("{struct_name} has no member {name}"))?;
+            offset += member_offset;
+            type_id = self.resolve(member_type);
+        }
+        if offset % 8 != 0 {
+            bail!("{struct_name}.{} is a bit field", path.join("."));
+        }
+        Ok(offset / 8)
+    }
+
+    fn find_member(&self, type_id: u32, name: &str) -> Option<(u32, u32)> {
+        for member in &self.types.get(type_id as usize)?.members {
+            if member.name == 0 {
+                if let Some((offset, type_id)) = self.find_member(self.resolve(member.type_id), name) {
+                    return Some((member.offset + offset, type_id));
+                }
+            } else if self.name(member.name) == name {
+                return Some((member.offset, member.type_id));
+            }
+        }
+        None
+    }
+
+    /// Follows typedefs and qualifiers to the type they refer to.
+    fn resolve(&self, mut type_id: u32) -> u32 {
+        while let Some(ty) = self.types.get(type_id as usize) {
+            match ty.kind {
+                BTF_KIND_TYPEDEF | BTF_KIND_VOLATILE | BTF_KIND_CONST | BTF_KIND_RESTRICT | BTF_KIND_TYPE_TAG => {
+                    type_id = ty.referenced
+                }
+                _ => break,
+            };
+        }
+        type_id
+    }
+
+    fn name(&self, offset: u32) -> Cow<'_, str> {
+        let name = self.strings.get(offset as usize..).unwrap_or_default();
+        let end = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+        String::from_utf8_lossy(&name[..end])
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn read<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        let bytes = self.data.get(self.position..self.position + N).ok_or_else(|| anyhow!("unexpected end of data"))?;
+        self.position += N;
+        Ok(bytes.try_into().expect("wrong size"))
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_ne_bytes(self.read()?))
+    }
+
+    fn skip(&mut self, length: usize) {
+        self.position += length;
+    }
+
+    fn btf_type(&mut self) -> anyhow::Result<BtfType> {
+        let name = self.u32()?;
+        let info = self.u32()?;
+        let referenced = self.u32()?;
+        let (count, kind, kind_flag) = ((info & 0xffff) as usize, (info >> 24) & 0x1f, info >> 31 == 1);
+        let mut members = Vec::new();
+        match kind {
+            BTF_KIND_INT | BTF_KIND_VAR | BTF_KIND_DECL_TAG => self.skip(4),
+            BTF_KIND_ARRAY => self.skip(12),
+            BTF_KIND_STRUCT | BTF_KIND_UNION => {
+                for _ in 0..count {
+                    let (name, type_id, offset) = (self.u32()?, self.u32()?, self.u32()?);
+                    // With the kind flag set, the upper 8 bits hold the size of bit fields.
+                    let offset = if kind_flag { offset & 0xffffff } else { offset };
+                    members.push(BtfMember { name, type_id, offset });
+                }
+            }
+            BTF_KIND_ENUM | BTF_KIND_FUNC_PROTO => self.skip(8 * count),
+            BTF_KIND_DATASEC | BTF_KIND_ENUM64 => self.skip(12 * count),
+            // The other kinds have no data after them.
+            _ => (),
+        };
+        Ok(BtfType { name, kind, referenced, members })
+    }
+}
+
+/// Finds where the fields the eBPF programs read are in the running kernel's structs.
+pub fn kernel_offsets() -> anyhow::Result<KernelOffsets> {
+    let btf = Btf::from_sys_fs()?;
+    let sock = |path: &[&str]| btf.member_offset("sock", path);
+    Ok(KernelOffsets {
+        socket_sk: btf.member_offset("socket", &["sk"])?,
+        sock_daddr: sock(&["__sk_common", "skc_daddr"])?,
+        sock_rcv_saddr: sock(&["__sk_common", "skc_rcv_saddr"])?,
+        sock_dport: sock(&["__sk_common", "skc_dport"])?,
+        sock_num: sock(&["__sk_common", "skc_num"])?,
+        sock_family: sock(&["__sk_common", "skc_family"])?,
+        sock_v6_daddr: sock(&["__sk_common", "skc_v6_daddr"])?,
+        sock_v6_rcv_saddr: sock(&["__sk_common", "skc_v6_rcv_saddr"])?,
+        // Without network namespaces, there's only the one every process is in.
+        sock_net: sock(&["__sk_common", "skc_net", "net"]).unwrap_or(0),
+        task_real_parent: btf.member_offset("task_struct", &["real_parent"])?,
+        task_tgid: btf.member_offset("task_struct", &["tgid"])?,
+        task_group_leader: btf.member_offset("task_struct", &["group_leader"])?,
+        // Renamed in 5.5.
+        task_start_time: btf
+            .member_offset("task_struct", &["start_boottime"])
+            .or_else(|_| btf.member_offset("task_struct", &["real_start_time"]))?,
+        task_nsproxy: btf.member_offset("task_struct", &["nsproxy"])?,
+        nsproxy_net_ns: btf.member_offset("nsproxy", &["net_ns"])?,
+        net_inum: btf.member_offset("net", &["ns", "inum"])?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds BTF data one type at a time, types being numbered from 1 in the order they're added.
+    #[derive(Default)]
+    struct Builder {
+        types: Vec<u8>,
+        strings: Vec<u8>,
+    }
+
+    impl Builder {
+        fn add(&mut self, name: &str, kind: u32, size_or_type: u32, data: &[u32]) -> &mut Self {
+            let name = self.string(name);
+            let count = if matches!(kind, BTF_KIND_STRUCT | BTF_KIND_UNION) { data.len() as u32 / 3 } else { 0 };
+            for value in [name, kind << 24 | count, size_or_type].iter().chain(data) {
+                self.types.extend(value.to_ne_bytes());
+            }
+            self
+        }
+
+        /// Adds a struct or union, whose members are given as their name, type and offset in bits.
+        fn add_members(&mut self, name: &str, kind: u32, size: u32, members: &[(&str, u32, u32)]) -> &mut Self {
+            let members: Vec<_> =
+                members.iter().flat_map(|(name, type_id, offset)| [self.string(name), *type_id, *offset]).collect();
+            self.add(name, kind, size, &members)
+        }
+
+        fn string(&mut self, string: &str) -> u32 {
+            if string.is_empty() {
+                return 0;
+            }
+            if self.strings.is_empty() {
+                self.strings.push(0);
+            }
+            let offset = self.strings.len() as u32;
+            self.strings.extend(string.as_bytes());
+            self.strings.push(0);
+            offset
+        }
+
+        fn build(&self) -> Btf {
+            let mut data = Vec::new();
+            data.extend(BTF_MAGIC.to_ne_bytes());
+            data.extend([1, 0]);
+            let lengths = [BTF_HEADER_SIZE, 0, self.types.len(), self.types.len(), self.strings.len()];
+            for value in lengths {
+                data.extend((value as u32).to_ne_bytes());
+            }
+            data.extend(&self.types);
+            data.extend(&self.strings);
+            Btf::parse(&data).expect("invalid BTF")
+        }
+    }
+
+    fn btf() -> Btf {
+        Builder::default()
+            // 1
+            .add("int", BTF_KIND_INT, 4, &[32])
+            // 2: a forward declaration, which is skipped
+            .add_members("sock", BTF_KIND_STRUCT, 0, &[])
+            // 3
+            .add_members("sock_common", BTF_KIND_STRUCT, 8, &[("skc_daddr", 1, 0), ("skc_rcv_saddr", 1, 32)])
+            // 4
+            .add("sock_common_t", BTF_KIND_TYPEDEF, 3, &[])
+            // 5: an anonymous struct in an anonymous union
+            .add_members("", BTF_KIND_STRUCT, 8, &[("skc_dport", 1, 0), ("skc_num", 1, 32)])
+            // 6
+            .add_members("", BTF_KIND_UNION, 8, &[("", 5, 0), ("skc_portpair", 1, 0)])
+            // 7
+            .add("", BTF_KIND_CONST, 4, &[])
+            // 8
+            .add_members(
+                "sock",
+                BTF_KIND_STRUCT,
+                32,
+                &[("sk_flags", 1, 0), ("", 6, 64), ("__sk_common", 7, 128), ("sk_bits", 1, 195)],
+            )
+            .build()
+    }
+
+    #[test]
+    fn member_offset_finds_members_of_anonymous_structs_and_unions() {
+        let btf = btf();
+        assert_eq!(btf.member_offset("sock", &["sk_flags"]).unwrap(), 0);
+        assert_eq!(btf.member_offset("sock", &["skc_portpair"]).unwrap(), 8);
+        assert_eq!(btf.member_offset("sock", &["skc_num"]).unwrap(), 12);
+    }
+
+    #[test]
+    fn member_offset_follows_typedefs_and_qualifiers_into_nested_structs() {
+        let btf = btf();
+        assert_eq!(btf.member_offset("sock", &["__sk_common", "skc_rcv_saddr"]).unwrap(), 20);
+        assert_eq!(btf.member_offset("sock_common", &["skc_daddr"]).unwrap(), 0);
+    }
+
+    #[test]
+    fn member_offset_rejects_missing_members_and_bit_fields() {
+        let btf = btf();
+        assert!(btf.member_offset("sock", &["sk_missing"]).is_err());
+        assert!(btf.member_offset("sock", &["sk_flags", "skc_num"]).is_err());
+        assert!(btf.member_offset("socket", &["sk"]).is_err());
+        assert!(btf.member_offset("sock", &["sk_bits"]).is_err());
+    }
+}
@@ -0,0 +1,468 @@
+use crate::{
+    bpf::BpfEvent,
+    clock::Clock,
+    container::{net_namespace, Workload, WorkloadTable},
+};
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+use sockwho_common::{
+    AddressFamily, Event, EventKind, ProcessExecEvent, SockaddrEvent, SocketCallEvent, SocketCreatedEvent,
+    SocketOptionEvent, SocketStateEvent, Syscall, EXEC_PATH_MAX, UNIX_PATH_MAX,
+};
+use std::{
+    ffi::CStr,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    mem::{offset_of, size_of, MaybeUninit},
+    path::Path,
+    process, slice,
+};
+
+// The bytes every capture starts with.
+const MAGIC: &[u8; 8] = b"SOCKWHO\0";
+
+// The version of the layout of capture files, which has to change whenever the layout of the events does.
+const FORMAT_VERSION: u32 = 1;
+
+// The size of every kind of event, in the order of `EventKind`. These are stored after the format version, so a change
+// to the layout of an event that changes its size is caught even if the format version wasn't changed along with it.
+const EVENT_SIZES: [u32; 6] = [
+    size_of::<SockaddrEvent>() as u32,
+    size_of::<SocketStateEvent>() as u32,
+    size_of::<SocketCreatedEvent>() as u32,
+    size_of::<SocketCallEvent>() as u32,
+    size_of::<SocketOptionEvent>() as u32,
+    size_of::<ProcessExecEvent>() as u32,
+];
+
+// The header and workload records are small, this only guards against allocating whatever a corrupted length says.
+const MAX_JSON_LENGTH: u32 = 64 * 1024;
+
+// The kind of the records that hold the workload behind a cgroup, which precede the first event in that cgroup. Unlike
+// events, they're stored as JSON.
+const WORKLOAD_RECORD: u32 = u32::MAX;
+
+// The number of values of the enums in the events, which are numbered from 0 by their last variant.
+const ADDRESS_FAMILIES: u8 = AddressFamily::Unspecified as u8 + 1;
+const SYSCALLS: u8 = Syscall::GetPeerName as u8 + 1;
+
+/// Describes the machine a capture was recorded on.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CaptureHeader {
+    /// The version of sockwho that recorded the capture.
+    pub version: String,
+
+    pub hostname: String,
+
+    /// The release of the kernel the machine was running, e.g. "6.1.0-18-amd64".
+    pub kernel: String,
+
+    /// The wall-clock time at which the machine booted, in nanoseconds since the epoch. Event timestamps are relative
+    /// to it.
+    pub boot_time: u64,
+
+    /// The byte order the events were stored in, which is the one of the machine they were recorded on.
+    pub byte_order: String,
+
+    /// The inode of the network namespace sockwho ran in, which the one of every event is displayed relative to.
+    pub net_namespace: Option<u64>,
+}
+
+impl CaptureHeader {
+    /// The header for a capture recorded on this machine.
+    pub fn current() -> anyhow::Result<Self> {
+        let mut uname = MaybeUninit::<libc::utsname>::uninit();
+        if unsafe { libc::uname(uname.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error()).context("reading kernel information");
+        }
+        let uname = unsafe { uname.assume_init() };
+        let text = |field: &[libc::c_char]| unsafe { CStr::from_ptr(field.as_ptr()) }.to_string_lossy().into_owned();
+        Ok(Self {
+            version: env!("CARGO_PKG_VERSION").into(),
+            hostname: text(&uname.nodename),
+            kernel: text(&uname.release),
+            boot_time: Clock::new().boot_time(),
+            byte_order: byte_order().into(),
+            net_namespace: net_namespace(process::id()),
+        })
+    }
+}
+
+/// The workload behind a cgroup, as recorded in a capture.
+#[derive(Serialize, Deserialize)]
+struct WorkloadRecord {
+    cgroup_id: u64,
+    workload: Option<Workload>,
+}
+
+/// What's known about the machine a capture was recorded on besides its events, so they're processed the same way as
+/// if they were being traced there.
+#[derive(Clone, Default)]
+pub struct RecordedMachine {
+    /// The workloads behind the cgroups of the events, which are filled in as the capture is read.
+    pub workloads: WorkloadTable,
+
+    /// The network namespace sockwho ran in.
+    pub net_namespace: Option<u64>,
+}
+
+/// Writes events to a capture file, as the raw records the eBPF programs generated.
+///
+/// A capture starts with a magic number, the format version and the size of every kind of event, followed by the length
+/// of the header and the header itself as JSON. Every event after it is preceded by its kind and its size, and the
+/// workload behind the cgroup of an event is written before it unless it already was.
+pub struct CaptureWriter<W: Write> {
+    writer: W,
+}
+
+impl CaptureWriter<BufWriter<File>> {
+    pub fn create(path: &Path, header: &CaptureHeader) -> anyhow::Result<Self> {
+        let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+        Self::new(BufWriter::new(file), header)
+    }
+}
+
+impl<W: Write> CaptureWriter<W> {
+    pub fn new(mut writer: W, header: &CaptureHeader) -> anyhow::Result<Self> {
+        let header = serde_json::to_vec(header)?;
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        for size in EVENT_SIZES {
+            writer.write_all(&size.to_le_bytes())?;
+        }
+        writer.write_all(&(header.len() as u32).to_le_bytes())?;
+        writer.write_all(&header)?;
+        Ok(Self { writer })
+    }
+
+    pub fn write(&mut self, event: &BpfEvent) -> anyhow::Result<()> {
+        let (kind, bytes) = match event {
+            BpfEvent::Sockaddr(event) => record(event),
+            BpfEvent::SocketState(event) => record(event),
+            BpfEvent::SocketCreated(event) => record(event),
+            BpfEvent::SocketCall(event) => record(event),
+            BpfEvent::SocketOption(event) => record(event),
+            BpfEvent::ProcessExec(event) => record(event),
+        };
+        self.writer.write_all(&(kind as u32).to_ne_bytes())?;
+        self.writer.write_all(&(bytes.len() as u32).to_ne_bytes())?;
+        self.writer.write_all(bytes)?;
+        Ok(())
+    }
+
+    /// Records the workload behind a cgroup, which applies to the events written after it.
+    pub fn write_workload(&mut self, cgroup_id: u64, workload: &Option<Workload>) -> anyhow::Result<()> {
+        let bytes = serde_json::to_vec(&WorkloadRecord { cgroup_id, workload: workload.clone() })?;
+        self.writer.write_all(&WORKLOAD_RECORD.to_ne_bytes())?;
+        self.writer.write_all(&(bytes.len() as u32).to_ne_bytes())?;
+        self.writer.write_all(&bytes)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Reads the events in a capture file.
+///
+/// The events are checked to be the size they should be and to only hold valid values for their enums and bools, so
+/// corrupted captures can't be turned into invalid events.
+pub struct CaptureReader<R: Read> {
+    reader: R,
+    header: CaptureHeader,
+    workloads: WorkloadTable,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut reader: R) -> anyhow::Result<Self> {
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic).context("reading capture header")?;
+        if magic != *MAGIC {
+            bail!("not a sockwho capture");
+        }
+        let version = u32::from_le_bytes(read_array(&mut reader)?);
+        if version != FORMAT_VERSION {
+            bail!("unsupported capture format version {version}, expected {FORMAT_VERSION}");
+        }
+        for expected in EVENT_SIZES {
+            let size = u32::from_le_bytes(read_array(&mut reader)?);
+            if size != expected {
+                bail!("capture was recorded by a version of sockwho whose events have a different layout");
+            }
+        }
+        let length = u32::from_le_bytes(read_array(&mut reader)?);
+        if length > MAX_JSON_LENGTH {
+            bail!("capture header is too large ({length} bytes)");
+        }
+        let mut header = vec![0; length as usize];
+        reader.read_exact(&mut header).context("reading capture header")?;
+        let header: CaptureHeader = serde_json::from_slice(&header).context("invalid capture header")?;
+        if header.byte_order != byte_order() {
+            bail!("capture was recorded on a {} endian machine", header.byte_order);
+        }
+        Ok(Self { reader, header, workloads: WorkloadTable::default() })
+    }
+
+    pub fn header(&self) -> &CaptureHeader {
+        &self.header
+    }
+
+    /// What's known about the machine the capture was recorded on. Its workloads are only known once the events in
+    /// them are read.
+    pub fn machine(&self) -> RecordedMachine {
+        RecordedMachine { workloads: self.workloads.clone(), net_namespace: self.header.net_namespace }
+    }
+
+    /// Reads the next event, if there's any left.
+    pub fn read(&mut self) -> anyhow::Result<Option<BpfEvent>> {
+        loop {
+            let mut kind = [0; 4];
+            match self.reader.read_exact(&mut kind) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            let kind = u32::from_ne_bytes(kind);
+            let size = u32::from_ne_bytes(read_array(&mut self.reader)?);
+            if kind != WORKLOAD_RECORD {
+                return self.read_event(kind, size).map(Some);
+            }
+            if size > MAX_JSON_LENGTH {
+                bail!("workload record is too large ({size} bytes)");
+            }
+            let mut bytes = vec![0; size as usize];
+            self.reader.read_exact(&mut bytes).context("reading workload")?;
+            let WorkloadRecord { cgroup_id, workload } =
+                serde_json::from_slice(&bytes).context("invalid workload record")?;
+            self.workloads.lock().expect("poisoned lock").insert(cgroup_id, workload);
+        }
+    }
+
+    fn read_event(&mut self, kind: u32, size: u32) -> anyhow::Result<BpfEvent> {
+        let expected = EVENT_SIZES.get(kind as usize).ok_or_else(|| anyhow!("unknown event kind {kind}"))?;
+        if size != *expected {
+            bail!("event of kind {kind} has the wrong size ({size} bytes)");
+        }
+        let mut bytes = vec![0; size as usize];
+        self.reader.read_exact(&mut bytes).context("reading event")?;
+        match kind {
+            kind if kind == EventKind::Sockaddr as u32 => parse::<SockaddrEvent>(&bytes),
+            kind if kind == EventKind::SocketState as u32 => parse::<SocketStateEvent>(&bytes),
+            kind if kind == EventKind::SocketCreated as u32 => parse::<SocketCreatedEvent>(&bytes),
+            kind if kind == EventKind::SocketCall as u32 => parse::<SocketCallEvent>(&bytes),
+            kind if kind == EventKind::SocketOption as u32 => parse::<SocketOptionEvent>(&bytes),
+            kind if kind == EventKind::ProcessExec as u32 => parse::<ProcessExecEvent>(&bytes),
+            kind => bail!("unknown event kind {kind}"),
+        }
+    }
+}
+
+/// The kind of an event and its raw bytes, the same way the eBPF programs send it.
+fn record<T: Event>(event: &T) -> (EventKind, &[u8]) {
+    let bytes = unsafe { slice::from_raw_parts(event as *const T as *const u8, size_of::<T>()) };
+    (T::KIND, bytes)
+}
+
+/// The bytes of an event that hold an enum or a bool, which only some values are valid for.
+trait RestrictedBytes: Event {
+    /// The offset of every such byte and the number of values that are valid for it.
+    const RESTRICTED: &'static [(usize, u8)];
+
+    /// Checks the values of the other fields that are relied on, e.g. the lengths of paths.
+    fn validate(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+impl RestrictedBytes for SockaddrEvent {
+    const RESTRICTED: &'static [(usize, u8)] = &[
+        (offset_of!(SockaddrEvent, family), ADDRESS_FAMILIES),
+        (offset_of!(SockaddrEvent, syscall), SYSCALLS),
+        (offset_of!(SockaddrEvent, implicit_peer), 2),
+    ];
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.path_length as usize > UNIX_PATH_MAX {
+            bail!("event of kind {:?} has a path longer than {UNIX_PATH_MAX} bytes", Self::KIND);
+        }
+        Ok(())
+    }
+}
+
+impl RestrictedBytes for SocketStateEvent {
+    const RESTRICTED: &'static [(usize, u8)] = &[(offset_of!(SocketStateEvent, family), ADDRESS_FAMILIES)];
+}
+
+impl RestrictedBytes for SocketCreatedEvent {
+    const RESTRICTED: &'static [(usize, u8)] = &[];
+}
+
+impl RestrictedBytes for SocketCallEvent {
+    const RESTRICTED: &'static [(usize, u8)] = &[(offset_of!(SocketCallEvent, syscall), SYSCALLS)];
+}
+
+impl RestrictedBytes for SocketOptionEvent {
+    const RESTRICTED: &'static [(usize, u8)] = &[];
+}
+
+impl RestrictedBytes for ProcessExecEvent {
+    const RESTRICTED: &'static [(usize, u8)] = &[];
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.path_length as usize > EXEC_PATH_MAX {
+            bail!("event of kind {:?} has a path longer than {EXEC_PATH_MAX} bytes", Self::KIND);
+        }
+        Ok(())
+    }
+}
+
+/// Builds an event out of its raw bytes, the same way the ones generated by the eBPF programs are.
+fn parse<T: RestrictedBytes>(bytes: &[u8]) -> anyhow::Result<BpfEvent>
+where
+    BpfEvent: From<T>,
+{
+    if bytes.len() != size_of::<T>() {
+        bail!("event of kind {:?} has the wrong size ({} bytes)", T::KIND, bytes.len());
+    }
+    // Reading an enum or a bool that holds any other value is undefined behavior.
+    for (offset, values) in T::RESTRICTED {
+        if bytes[*offset] >= *values {
+            bail!("event of kind {:?} has an invalid value at byte {offset}", T::KIND);
+        }
+    }
+    let event = unsafe { (bytes.as_ptr() as *const T).read_unaligned() };
+    event.validate()?;
+    Ok(BpfEvent::from(event))
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> anyhow::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes).context("reading capture")?;
+    Ok(bytes)
+}
+
+fn byte_order() -> &'static str {
+    if cfg!(target_endian = "little") {
+        "little"
+    } else {
+        "big"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> CaptureHeader {
+        CaptureHeader {
+            version: "0.1.0".into(),
+            hostname: "host".into(),
+            kernel: "6.1.0".into(),
+            boot_time: 1,
+            byte_order: byte_order().into(),
+            net_namespace: Some(4026531840),
+        }
+    }
+
+    fn sockaddr(path: &str) -> SockaddrEvent {
+        let mut event: SockaddrEvent = unsafe { std::mem::zeroed() };
+        event.pid = 42;
+        event.family = AddressFamily::Unix;
+        event.syscall = Syscall::Connect;
+        event.path[..path.len()].copy_from_slice(path.as_bytes());
+        event.path_length = path.len() as u8;
+        event
+    }
+
+    fn capture(events: &[BpfEvent]) -> Vec<u8> {
+        let mut writer = CaptureWriter::new(Vec::new(), &header()).expect("writing header");
+        for event in events {
+            writer.write(event).expect("writing event");
+        }
+        writer.writer
+    }
+
+    fn read_all(bytes: &[u8]) -> anyhow::Result<Vec<BpfEvent>> {
+        let mut reader = CaptureReader::new(bytes)?;
+        let mut events = Vec::new();
+        while let Some(event) = reader.read()? {
+            events.push(event);
+        }
+        Ok(events)
+    }
+
+    fn error(bytes: &[u8]) -> String {
+        read_all(bytes).expect_err("capture should be rejected").to_string()
+    }
+
+    // The offset of the first event in a capture holding the test header.
+    fn first_event() -> usize {
+        MAGIC.len() + 4 * (2 + EVENT_SIZES.len()) + serde_json::to_vec(&header()).unwrap().len()
+    }
+
+    #[test]
+    fn events_and_workloads_round_trip() {
+        let mut writer = CaptureWriter::new(Vec::new(), &header()).unwrap();
+        let workload = Some(Workload::Unit("nginx.service".into()));
+        writer.write_workload(7, &workload).unwrap();
+        writer.write(&BpfEvent::Sockaddr(sockaddr("/run/app.sock"))).unwrap();
+        let mut reader = CaptureReader::new(writer.writer.as_slice()).unwrap();
+        assert_eq!(reader.header().hostname, "host");
+        assert_eq!(reader.header().net_namespace, Some(4026531840));
+
+        let Some(BpfEvent::Sockaddr(event)) = reader.read().unwrap() else { panic!("expected a sockaddr event") };
+        assert_eq!(event.pid, 42);
+        assert!(matches!(event.syscall, Syscall::Connect));
+        assert_eq!(&event.path[..event.path_length as usize], b"/run/app.sock");
+        assert!(reader.read().unwrap().is_none());
+        assert_eq!(reader.machine().workloads.lock().unwrap().get(&7), Some(&workload));
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut bytes = capture(&[]);
+        bytes[0] = b'X';
+        assert_eq!(error(&bytes), "not a sockwho capture");
+    }
+
+    #[test]
+    fn rejects_different_event_sizes_in_header() {
+        let mut bytes = capture(&[]);
+        bytes[MAGIC.len() + 4] ^= 1;
+        assert!(error(&bytes).contains("different layout"));
+    }
+
+    #[test]
+    fn rejects_events_of_the_wrong_size() {
+        let mut bytes = capture(&[BpfEvent::Sockaddr(sockaddr("/run/app.sock"))]);
+        let size = first_event() + 4;
+        bytes[size..size + 4].copy_from_slice(&(EVENT_SIZES[0] - 1).to_ne_bytes());
+        assert!(error(&bytes).contains("wrong size"));
+    }
+
+    #[test]
+    fn rejects_invalid_enum_values() {
+        let mut bytes = capture(&[BpfEvent::Sockaddr(sockaddr("/run/app.sock"))]);
+        bytes[first_event() + 8 + offset_of!(SockaddrEvent, syscall)] = SYSCALLS;
+        assert!(error(&bytes).contains("invalid value"));
+    }
+
+    #[test]
+    fn rejects_paths_longer_than_their_buffer() {
+        let mut event = sockaddr("/run/app.sock");
+        event.path_length = UNIX_PATH_MAX as u8 + 1;
+        assert!(error(&capture(&[BpfEvent::Sockaddr(event)])).contains("path longer than"));
+
+        let mut event: ProcessExecEvent = unsafe { std::mem::zeroed() };
+        event.path_length = EXEC_PATH_MAX as u32 + 1;
+        assert!(error(&capture(&[BpfEvent::ProcessExec(event)])).contains("path longer than"));
+    }
+}
@@ -0,0 +1,90 @@
+use std::{fmt, mem::MaybeUninit, time::Duration};
+
+/// Converts the timestamps in events, which use the kernel's monotonic clock, into wall-clock time.
+#[derive(Clone, Copy, Debug)]
+pub struct Clock {
+    // The wall-clock time at which the monotonic clock started, in nanoseconds since the epoch.
+    boot_time: u64,
+
+    // The wall-clock time at which the machine booted, which times read from /proc count from. It's earlier than when
+    // the monotonic clock started by the time the machine spent suspended, which the monotonic clock doesn't count.
+    suspended_boot_time: u64,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        let now = read_clock(libc::CLOCK_REALTIME);
+        let boot_time = now.saturating_sub(monotonic_now());
+        let suspended_boot_time = now.saturating_sub(read_clock(libc::CLOCK_BOOTTIME));
+        Self { boot_time, suspended_boot_time }
+    }
+
+    /// A clock for a machine that booted at the given time, in nanoseconds since the epoch.
+    pub fn from_boot_time(boot_time: u64) -> Self {
+        // Nothing is read from /proc for such machines, e.g. when replaying a capture.
+        Self { boot_time, suspended_boot_time: boot_time }
+    }
+
+    /// The wall-clock time at which the machine booted, in nanoseconds since the epoch.
+    pub fn boot_time(&self) -> u64 {
+        self.boot_time
+    }
+
+    /// Converts a timestamp into nanoseconds since the epoch.
+    pub fn wall_clock(&self, timestamp: u64) -> u64 {
+        self.boot_time + timestamp
+    }
+
+    /// Converts a time read from /proc, in nanoseconds since boot including the time spent suspended, into
+    /// nanoseconds since the epoch.
+    pub fn wall_clock_since_boot(&self, time: u64) -> u64 {
+        self.suspended_boot_time + time
+    }
+
+    /// Displays a timestamp as local time.
+    pub fn display(&self, timestamp: u64) -> TimestampDisplay {
+        TimestampDisplay(self.wall_clock(timestamp))
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The current time on the same clock the eBPF programs use, in nanoseconds.
+pub fn monotonic_now() -> u64 {
+    read_clock(libc::CLOCK_MONOTONIC)
+}
+
+fn read_clock(clock: libc::clockid_t) -> u64 {
+    let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe { libc::clock_gettime(clock, &mut time) };
+    time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64
+}
+
+/// Displays a wall-clock time the same way `strace -tt` does, e.g. `10:31:05.123456`.
+pub struct TimestampDisplay(pub u64);
+
+impl fmt::Display for TimestampDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = (self.0 / 1_000_000_000) as libc::time_t;
+        let micros = self.0 % 1_000_000_000 / 1000;
+        let mut local = MaybeUninit::<libc::tm>::uninit();
+        if unsafe { libc::localtime_r(&seconds, local.as_mut_ptr()) }.is_null() {
+            return write!(f, "{seconds}.{micros:06}");
+        }
+        let local = unsafe { local.assume_init() };
+        write!(f, "{:02}:{:02}:{:02}.{micros:06}", local.tm_hour, local.tm_min, local.tm_sec)
+    }
+}
+
+/// Displays the time a syscall took the same way `strace -T` does, e.g. `<0.000021>`.
+pub struct DurationDisplay(pub u64);
+
+impl fmt::Display for DurationDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{:.6}>", Duration::from_nanos(self.0).as_secs_f64())
+    }
+}
@@ -0,0 +1,278 @@
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    num::NonZeroUsize,
+    os::unix::fs::MetadataExt,
+    path::Path,
+    sync::{
+        mpsc::{channel, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+// The length of the container ids displayed, the same one docker uses.
+const SHORT_ID_LENGTH: usize = 12;
+const PROCESS_CACHE_SIZE: usize = 4096;
+
+/// The workloads behind cgroups, indexed by cgroup id.
+pub type WorkloadTable = Arc<Mutex<HashMap<u64, Option<Workload>>>>;
+
+/// What a cgroup belongs to, e.g. a docker container or a systemd service.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Workload {
+    /// A container, along with the runtime that created it if it can be told from the cgroup's path.
+    Container { runtime: Option<String>, id: String },
+
+    /// A systemd unit, e.g. a service, a login session's scope or a slice.
+    Unit(String),
+}
+
+impl Workload {
+    /// Works out the workload from the path of a cgroup, relative to the root of the hierarchy.
+    pub fn from_cgroup_path(path: &str) -> Option<Self> {
+        // Containers can create cgroups of their own, so any directory along the path can name the container.
+        let components: Vec<_> = path.split('/').filter(|component| !component.is_empty()).collect();
+        for (index, component) in components.iter().enumerate().rev() {
+            let parent = index.checked_sub(1).map(|index| components[index]);
+            if let Some(container) = Self::parse_container(component, parent) {
+                return Some(container);
+            }
+        }
+        components
+            .iter()
+            .rev()
+            .find(|component| [".service", ".scope", ".slice"].iter().any(|suffix| component.ends_with(suffix)))
+            .map(|unit| Self::Unit(unit.to_string()))
+    }
+
+    /// Whether this is the workload given by the user, either as a (prefix of a) container id or a unit name.
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            Self::Container { id, .. } => !name.is_empty() && id.starts_with(name),
+            Self::Unit(unit) => unit == name || unit.rsplit_once('.').is_some_and(|(unit, _)| unit == name),
+        }
+    }
+
+    fn parse_container(component: &str, parent: Option<&str>) -> Option<Self> {
+        let name = component.strip_suffix(".scope").unwrap_or(component);
+        // systemd managed cgroups look like `docker-<id>.scope`, cgroupfs managed ones like `docker/<id>`.
+        let (runtime, id) = match name.rsplit_once('-') {
+            Some(("docker", id)) => (Some("docker"), id),
+            Some(("cri-containerd", id)) => (Some("containerd"), id),
+            Some(("crio", id)) => (Some("cri-o"), id),
+            Some(("libpod", id)) => (Some("podman"), id),
+            _ if parent == Some("docker") => (Some("docker"), name),
+            _ => (None, name),
+        };
+        is_container_id(id).then(|| Self::Container { runtime: runtime.map(String::from), id: id.to_string() })
+    }
+}
+
+impl fmt::Display for Workload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Container { runtime: Some(runtime), id } => write!(f, "container={runtime}:{}", short_id(id)),
+            Self::Container { runtime: None, id } => write!(f, "container={}", short_id(id)),
+            Self::Unit(unit) => write!(f, "unit={unit}"),
+        }
+    }
+}
+
+/// Resolves the cgroups processes belong to into the workloads they're part of.
+pub struct CgroupResolver {
+    // The workload behind every cgroup found in cgroupfs, indexed by the cgroup id. cgroupfs is walked by a background
+    // thread given that takes a while on hosts with many cgroups.
+    workloads: WorkloadTable,
+    // Asks the background thread to walk cgroupfs again. Resolvers that don't look anything up on this machine don't
+    // have one.
+    scans: Option<Sender<()>>,
+    // The cgroups that weren't found in cgroupfs, so it's only walked again once for each of them.
+    requested: HashSet<u64>,
+    // The workloads looked up via /proc for the processes whose cgroup isn't in cgroupfs.
+    processes: LruCache<u32, Option<Workload>>,
+}
+
+impl CgroupResolver {
+    pub fn new() -> Self {
+        // Hosts using both cgroup versions mount the v2 hierarchy, which is the one cgroup ids refer to, separately.
+        let root = match Path::new("/sys/fs/cgroup/unified").is_dir() {
+            true => "/sys/fs/cgroup/unified",
+            false => "/sys/fs/cgroup",
+        };
+        let workloads = Arc::new(Mutex::new(HashMap::new()));
+        let (scans, requests) = channel();
+        let scanned = workloads.clone();
+        // The thread exits once the resolver is dropped.
+        thread::spawn(move || {
+            while requests.recv().is_ok() {
+                // The cgroups created while walking are found by the next walk, so the requests made until now are
+                // all handled by a single one.
+                while requests.try_recv().is_ok() {}
+                let found = scan(root);
+                let mut workloads = scanned.lock().expect("poisoned lock");
+                for (cgroup_id, workload) in found {
+                    workloads.entry(cgroup_id).or_insert(workload);
+                }
+            }
+        });
+        let _ = scans.send(());
+        Self {
+            workloads,
+            scans: Some(scans),
+            requested: HashSet::new(),
+            processes: LruCache::new(NonZeroUsize::new(PROCESS_CACHE_SIZE).expect("empty cache")),
+        }
+    }
+
+    /// A resolver that only knows about the workloads in a table filled in by someone else, e.g. the ones recorded in a
+    /// capture, rather than the ones on this machine.
+    pub fn offline(workloads: WorkloadTable) -> Self {
+        Self {
+            workloads,
+            scans: None,
+            requested: HashSet::new(),
+            // Never used given processes aren't looked up.
+            processes: LruCache::new(NonZeroUsize::MIN),
+        }
+    }
+
+    /// The workload a process was part of, given the cgroup it was in.
+    ///
+    /// The cgroup is looked up in cgroupfs so this works for processes that already exited. Until it's found there,
+    /// e.g. because it was just created or because the host only uses cgroup v1, the cgroup the process is in right now
+    /// is used instead.
+    pub fn workload(&mut self, cgroup_id: u64, pid: u32) -> Option<Workload> {
+        if let Some(workload) = self.workloads.lock().expect("poisoned lock").get(&cgroup_id) {
+            return workload.clone();
+        }
+        let scans = self.scans.as_ref()?;
+        // The cgroup was created after the last time cgroupfs was walked.
+        if self.requested.insert(cgroup_id) {
+            let _ = scans.send(());
+        }
+        self.processes
+            .get_or_insert(pid, || process_cgroup(pid).and_then(|path| Workload::from_cgroup_path(&path)))
+            .clone()
+    }
+}
+
+impl Default for CgroupResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Finds the network namespace a process is in, identified by its inode.
+pub fn net_namespace(pid: u32) -> Option<u64> {
+    // The link looks like `net:[4026531840]`.
+    let link = fs::read_link(format!("/proc/{pid}/ns/net")).ok()?;
+    let link = link.to_string_lossy();
+    link.strip_prefix("net:[")?.strip_suffix(']')?.parse().ok()
+}
+
+/// Walks cgroupfs, finding the workload behind every cgroup in it.
+fn scan(root: &str) -> Vec<(u64, Option<Workload>)> {
+    let mut workloads = Vec::new();
+    let mut directories = vec![root.to_string()];
+    while let Some(directory) = directories.pop() {
+        let Ok(entries) = fs::read_dir(&directory) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if !metadata.is_dir() {
+                continue;
+            }
+            let path = entry.path().to_string_lossy().into_owned();
+            // A cgroup's id is the inode of its directory.
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            workloads.push((metadata.ino(), Workload::from_cgroup_path(relative)));
+            directories.push(path);
+        }
+    }
+    workloads
+}
+
+/// Finds the path of the cgroup a process is in right now by looking it up in /proc.
+fn process_cgroup(pid: u32) -> Option<String> {
+    let cgroups = fs::read_to_string(format!("/proc/{pid}/cgroup")).ok()?;
+    // Every line looks like `<hierarchy id>:<controllers>:<path>`. The v2 hierarchy has id 0 and no controllers, and
+    // on cgroup v1 the systemd hierarchy is the one that names units and containers.
+    let paths: Vec<_> = cgroups
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ':').skip(1);
+            Some((fields.next()?, fields.next()?))
+        })
+        .collect();
+    paths
+        .iter()
+        .find(|(controllers, _)| controllers.is_empty())
+        .or_else(|| paths.iter().find(|(controllers, _)| *controllers == "name=systemd"))
+        .or(paths.first())
+        .map(|(_, path)| path.to_string())
+}
+
+fn is_container_id(id: &str) -> bool {
+    id.len() == 64 && id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn short_id(id: &str) -> &str {
+    &id[..SHORT_ID_LENGTH.min(id.len())]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "4f1e9a0c2b7d8e6f5a4b3c2d1e0f9a8b7c6d5e4f3a2b1c0d9e8f7a6b5c4d3e2f";
+
+    fn container(runtime: Option<&str>) -> Option<Workload> {
+        Some(Workload::Container { runtime: runtime.map(String::from), id: ID.into() })
+    }
+
+    #[test]
+    fn from_cgroup_path_finds_docker_containers() {
+        assert_eq!(Workload::from_cgroup_path(&format!("/system.slice/docker-{ID}.scope")), container(Some("docker")));
+        assert_eq!(Workload::from_cgroup_path(&format!("/docker/{ID}")), container(Some("docker")));
+        // Cgroups created by the container itself.
+        assert_eq!(Workload::from_cgroup_path(&format!("/docker/{ID}/init.scope")), container(Some("docker")));
+    }
+
+    #[test]
+    fn from_cgroup_path_finds_kubernetes_containers() {
+        let containerd =
+            format!("/kubepods.slice/kubepods-burstable.slice/kubepods-pod1.slice/cri-containerd-{ID}.scope");
+        assert_eq!(Workload::from_cgroup_path(&containerd), container(Some("containerd")));
+        assert_eq!(Workload::from_cgroup_path(&format!("/kubepods/burstable/pod1/{ID}")), container(None));
+        assert_eq!(Workload::from_cgroup_path(&format!("/machine.slice/libpod-{ID}.scope")), container(Some("podman")));
+    }
+
+    #[test]
+    fn from_cgroup_path_falls_back_to_systemd_units() {
+        let unit = |unit: &str| Some(Workload::Unit(unit.into()));
+        assert_eq!(Workload::from_cgroup_path("/system.slice/nginx.service"), unit("nginx.service"));
+        assert_eq!(Workload::from_cgroup_path("/user.slice/user-1000.slice/session-2.scope"), unit("session-2.scope"));
+        assert_eq!(Workload::from_cgroup_path("/system.slice"), unit("system.slice"));
+        // Not a full container id.
+        assert_eq!(Workload::from_cgroup_path("/system.slice/docker-4f1e9a0c.scope"), unit("docker-4f1e9a0c.scope"));
+        assert_eq!(Workload::from_cgroup_path("/"), None);
+    }
+
+    #[test]
+    fn matches_id_prefixes_and_unit_names() {
+        let workload = container(Some("docker")).unwrap();
+        assert!(workload.matches("4f1e9a0c2b7d"));
+        assert!(!workload.matches(""));
+        assert!(!workload.matches("4f1e9a0c2b7e"));
+        let unit = Workload::Unit("nginx.service".into());
+        assert!(unit.matches("nginx") && unit.matches("nginx.service"));
+        assert!(!unit.matches("nginx.scope"));
+    }
+}
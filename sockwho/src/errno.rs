@@ -1,8 +1,13 @@
 use enum_primitive_derive::Primitive;
+use num_traits::FromPrimitive;
+use serde::Serialize;
+
+// One past the largest errno value, ERFKILL, which the enum below goes up to.
+const ERRNO_MAX: i64 = 133;
 
 #[allow(clippy::upper_case_acronyms)]
-#[derive(Debug, Primitive)]
-pub(crate) enum Errno {
+#[derive(Clone, Copy, Debug, Primitive, Serialize)]
+pub enum Errno {
     EPERM = 1,
     ENOENT = 2,
     ESRCH = 3,
@@ -134,3 +139,33 @@ pub(crate) enum Errno {
     ENOTRECOVERABLE = 131,
     ERFKILL = 132,
 }
+
+impl Errno {
+    /// Finds an errno by its name, e.g. "ECONNREFUSED", ignoring case.
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        (1..ERRNO_MAX).filter_map(Self::from_i64).find(|errno| format!("{errno:?}").eq_ignore_ascii_case(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_name_ignores_case() {
+        assert!(matches!(Errno::from_name("ECONNREFUSED"), Some(Errno::ECONNREFUSED)));
+        assert!(matches!(Errno::from_name("econnrefused"), Some(Errno::ECONNREFUSED)));
+    }
+
+    #[test]
+    fn from_name_finds_the_first_and_last_errno() {
+        assert!(matches!(Errno::from_name("EPERM"), Some(Errno::EPERM)));
+        assert!(matches!(Errno::from_name("ERFKILL"), Some(Errno::ERFKILL)));
+    }
+
+    #[test]
+    fn from_name_rejects_unknown_names() {
+        assert!(Errno::from_name("EFOO").is_none());
+        assert!(Errno::from_name("").is_none());
+    }
+}
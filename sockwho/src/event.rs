@@ -0,0 +1,411 @@
+use crate::{
+    clock::{DurationDisplay, TimestampDisplay},
+    container::Workload,
+    errno::Errno,
+    flows::{CloseReason, Direction},
+    options::SocketOption,
+    processor::TcpState,
+    socket::SocketKind,
+};
+use serde::{Serialize, Serializer};
+use sockwho_common::AddressFamily;
+use std::{fmt, net::IpAddr, time::Duration};
+
+/// An event generated by our eBPF probes once decoded, along with what's known about the process behind it.
+#[derive(Clone, Debug, Serialize)]
+pub struct Event {
+    /// The time at which the event happened, in nanoseconds since the epoch.
+    pub timestamp: u64,
+
+    #[serde(flatten)]
+    pub process: Process,
+
+    #[serde(flatten)]
+    pub details: EventDetails,
+}
+
+/// The process behind an event.
+#[derive(Clone, Debug, Serialize)]
+pub struct Process {
+    pub pid: u32,
+
+    /// The name of the thread that generated the event.
+    pub comm: String,
+
+    /// Only known for events generated by the socket probes.
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<Identity>,
+
+    /// Only known when enriching events with the process information in /proc.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub info: Option<ProcessDetails>,
+}
+
+/// The thread, parent process, user and workload behind an event.
+#[derive(Clone, Debug, Serialize)]
+pub struct Identity {
+    pub tid: u32,
+
+    /// Read by the eBPF programs, so it's not known if the kernel's BTF isn't available.
+    pub ppid: Option<u32>,
+
+    pub uid: u32,
+    pub gid: u32,
+
+    /// The container or systemd unit the process belongs to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workload: Option<Workload>,
+
+    /// The inode of the network namespace the process is in, only set if it isn't the one we're in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub net_namespace: Option<u64>,
+}
+
+/// The information about a process read from /proc.
+#[derive(Clone, Debug, Serialize)]
+pub struct ProcessDetails {
+    pub name: Option<String>,
+    pub executable: Option<String>,
+    pub command_line: Option<String>,
+
+    /// The time at which the process started, in nanoseconds since the epoch.
+    pub start_time: Option<u64>,
+}
+
+/// What happened.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EventDetails {
+    /// A syscall on a socket returned.
+    Syscall(Box<SyscallEvent>),
+
+    /// A TCP connection changed state.
+    StateChange(StateChangeEvent),
+
+    /// A process executed a program.
+    Exec(ExecEvent),
+
+    /// A TCP connection was closed.
+    Flow(FlowEvent),
+}
+
+/// A syscall on a socket.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SyscallEvent {
+    /// The name of the syscall, e.g. "connect" or "get_sock_name".
+    pub syscall: &'static str,
+
+    /// The socket's file descriptor. Not set for socket(2), which returns it instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fd: Option<u32>,
+
+    /// A short name that describes the socket, e.g. "tcp", if it was created while tracing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub socket_kind: Option<String>,
+
+    /// The address used by the syscall.
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub address: Option<SyscallAddress>,
+
+    /// The arguments socket(2) was called with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub socket: Option<SocketKind>,
+
+    /// The option set via setsockopt(2).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub option: Option<SocketOption>,
+
+    /// The integer argument taken by syscalls like listen(2) or shutdown(2).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub argument: Option<i64>,
+
+    pub return_value: i64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errno: Option<Errno>,
+
+    /// How long the syscall took, in nanoseconds.
+    pub duration: u64,
+
+    /// The address the socket was bound to, for getsockname(2) calls that return a different one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bound_address: Option<String>,
+
+    /// The options that affect addressing set on the socket, for bind(2) and connect(2).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<SocketOption>,
+
+    /// How long the socket lived, in nanoseconds, for close(2) on sockets created while tracing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lifetime: Option<u64>,
+}
+
+/// The address used by a syscall.
+#[derive(Clone, Debug, Serialize)]
+pub struct SyscallAddress {
+    #[serde(serialize_with = "serialize_family")]
+    pub family: AddressFamily,
+
+    /// The IP address, for inet sockets.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<IpAddr>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+
+    /// The path, for Unix domain sockets, formatted the same way ss(8) does.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+
+    /// The local address, for syscalls on connected sockets that weren't given an address, in which case the address
+    /// above is the peer's.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_address: Option<IpAddr>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_port: Option<u16>,
+}
+
+/// A TCP connection changing state.
+#[derive(Clone, Debug, Serialize)]
+pub struct StateChangeEvent {
+    #[serde(serialize_with = "serialize_family")]
+    pub family: AddressFamily,
+    pub source_address: IpAddr,
+    pub source_port: u16,
+    pub destination_address: IpAddr,
+    pub destination_port: u16,
+    pub old_state: TcpState,
+    pub new_state: TcpState,
+}
+
+/// A process executing a program.
+#[derive(Clone, Debug, Serialize)]
+pub struct ExecEvent {
+    /// The path to the program.
+    pub path: String,
+}
+
+/// A summary of a TCP connection, from the time it was opened until it was closed.
+#[derive(Clone, Debug, Serialize)]
+pub struct FlowEvent {
+    #[serde(serialize_with = "serialize_family")]
+    pub family: AddressFamily,
+    pub direction: Direction,
+
+    /// The socket's file descriptor, if the call to connect(2) or accept(2) behind the connection was seen.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fd: Option<u32>,
+
+    pub local_address: IpAddr,
+    pub local_port: u16,
+    pub remote_address: IpAddr,
+    pub remote_port: u16,
+
+    /// The value connect(2) returned, for outbound connections.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_result: Option<i64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_errno: Option<Errno>,
+
+    /// How long the handshake took, in nanoseconds, for connections that were established.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handshake: Option<u64>,
+
+    /// How long the connection lived for, in nanoseconds.
+    pub lifetime: u64,
+
+    pub close_reason: CloseReason,
+}
+
+/// Displays an event as a single line, e.g.
+/// `10:31:05.120902 curl/1234/5 tcp syscall::connect(1.1.1.1:443) = 0 <0.000009> ppid=1200 tid=1234 uid=1000 gid=1000`.
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Process { pid, comm, identity, info } = &self.process;
+        write!(f, "{} {comm}/{pid}", TimestampDisplay(self.timestamp))?;
+        match &self.details {
+            EventDetails::Syscall(event) => write!(f, "{event}")?,
+            EventDetails::StateChange(event) => write!(f, "{event}")?,
+            EventDetails::Exec(ExecEvent { path }) => write!(f, " process::exec({path})")?,
+            EventDetails::Flow(event) => write!(f, "{event}")?,
+        };
+        if let Some(identity) = identity {
+            write!(f, "{identity}")?;
+        }
+        if let Some(info) = info {
+            write!(f, "{info}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SyscallEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            syscall,
+            fd,
+            socket_kind,
+            address,
+            socket,
+            option,
+            argument,
+            return_value,
+            errno,
+            duration,
+            bound_address,
+            options,
+            lifetime,
+        } = self;
+        if let Some(fd) = fd {
+            write!(f, "/{fd}")?;
+        }
+        if let Some(socket_kind) = socket_kind {
+            write!(f, " {socket_kind}")?;
+        }
+        write!(f, " syscall::{syscall}(")?;
+        if let Some(address) = address {
+            write!(f, "{address}")?;
+        } else if let Some(socket) = socket {
+            write!(f, "{socket}")?;
+        } else if let Some(option) = option {
+            write!(f, "{option}")?;
+        } else if let Some(argument) = argument {
+            match (*syscall, argument) {
+                ("shutdown", 0) => write!(f, "SHUT_RD")?,
+                ("shutdown", 1) => write!(f, "SHUT_WR")?,
+                ("shutdown", 2) => write!(f, "SHUT_RDWR")?,
+                (_, argument) => write!(f, "{argument}")?,
+            };
+        }
+        write!(f, ") = {return_value}")?;
+        if let Some(errno) = errno {
+            write!(f, " [{errno:?}]")?;
+        }
+        write!(f, " {}", DurationDisplay(*duration))?;
+        if let Some(bound_address) = bound_address {
+            write!(f, " bind={bound_address}")?;
+        }
+        if !options.is_empty() {
+            let options: Vec<_> = options.iter().map(|option| format!("{}={}", option.name(), option.value)).collect();
+            write!(f, " opts=[{}]", options.join(","))?;
+        }
+        if let Some(lifetime) = lifetime {
+            write!(f, " lived={:.3?}", Duration::from_nanos(*lifetime))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SyscallAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let (Some(address), Some(port)) = (self.local_address, self.local_port) {
+            write!(f, "implicit {address}:{port} <-> ")?;
+        }
+        match (&self.address, self.port, &self.path) {
+            (Some(address), Some(port), _) => write!(f, "{address}:{port}"),
+            (_, _, Some(path)) => write!(f, "{path}"),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for StateChangeEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { source_address, source_port, destination_address, destination_port, old_state, new_state, .. } =
+            self;
+        write!(
+            f,
+            " socket::set_state({source_address}:{source_port} <-> {destination_address}:{destination_port}) {old_state:?} -> {new_state:?}"
+        )
+    }
+}
+
+impl fmt::Display for FlowEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            fd,
+            local_address,
+            local_port,
+            remote_address,
+            remote_port,
+            direction,
+            connect_result,
+            connect_errno,
+            handshake,
+            lifetime,
+            close_reason,
+            ..
+        } = self;
+        if let Some(fd) = fd {
+            write!(f, "/{fd}")?;
+        }
+        let arrow = match direction {
+            Direction::Outbound => "->",
+            Direction::Inbound => "<-",
+        };
+        write!(f, " flow::tcp({local_address}:{local_port} {arrow} {remote_address}:{remote_port}) {direction}")?;
+        if let Some(connect_result) = connect_result {
+            write!(f, " connect={connect_result}")?;
+        }
+        if let Some(errno) = connect_errno {
+            write!(f, " [{errno:?}]")?;
+        }
+        if let Some(handshake) = handshake {
+            write!(f, " handshake={}", DurationDisplay(*handshake))?;
+        }
+        write!(f, " lived={:.3?} reason={close_reason}", Duration::from_nanos(*lifetime))
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { tid, ppid, uid, gid, workload, net_namespace } = self;
+        match ppid {
+            Some(ppid) => write!(f, " ppid={ppid}")?,
+            None => write!(f, " ppid=?")?,
+        };
+        write!(f, " tid={tid} uid={uid} gid={gid}")?;
+        if let Some(workload) = workload {
+            write!(f, " {workload}")?;
+        }
+        if let Some(net_namespace) = net_namespace {
+            write!(f, " netns={net_namespace}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ProcessDetails {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { name, executable, command_line, start_time } = self;
+        if let Some(name) = name {
+            write!(f, " name={name}")?;
+        }
+        if let Some(executable) = executable {
+            write!(f, " exe={executable}")?;
+        }
+        if let Some(command_line) = command_line {
+            write!(f, " cmdline={command_line:?}")?;
+        }
+        if let Some(start_time) = start_time {
+            write!(f, " started={}", TimestampDisplay(*start_time))?;
+        }
+        Ok(())
+    }
+}
+
+/// The name of an address family, e.g. "ipv4".
+pub(crate) fn family_name(family: &AddressFamily) -> &'static str {
+    match family {
+        AddressFamily::Ipv4 => "ipv4",
+        AddressFamily::Ipv6 => "ipv6",
+        AddressFamily::Unix => "unix",
+        AddressFamily::Unspecified => "unspecified",
+    }
+}
+
+fn serialize_family<S: Serializer>(family: &AddressFamily, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(family_name(family))
+}
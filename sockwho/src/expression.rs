@@ -0,0 +1,717 @@
+use crate::{
+    bpf::BpfEvent,
+    errno::Errno,
+    filters::{KernelFilters, Network, COMMAND_MAX},
+    processor::{parse_address, parse_command, syscall_name, unix_path, TcpState},
+};
+use anyhow::{anyhow, bail, Context};
+use num_traits::FromPrimitive;
+use regex::Regex;
+use sockwho_common::{AddressFamily, FILTER_COMMAND, FILTER_NETWORK, FILTER_PID, FILTER_PORT};
+use std::{iter::Peekable, net::IpAddr, str::FromStr, vec::IntoIter};
+
+/// A tcpdump-like expression that events are matched against, e.g.
+/// `syscall == connect and port == 443 and errno != EINPROGRESS and not comm ~ "^chrome"`.
+///
+/// Comparisons are combined using `and`, `or` and `not`, and grouped using parentheses. A comparison on a field an event
+/// doesn't have is false, and `!=` and `!~` are always the opposite of `==` and `~`. Fields with more than one value,
+/// like the ports of a connection, are equal to anything any of them is equal to.
+#[derive(Clone, Debug)]
+pub struct FilterExpression(Node);
+
+impl FilterExpression {
+    /// Whether an event matches this expression.
+    pub fn matches(&self, event: &BpfEvent) -> bool {
+        self.0.matches(&EventFields::new(event))
+    }
+
+    /// Adds the parts of this expression that can be checked by the eBPF programs to the filters they apply, so the
+    /// events that can't match it never reach userspace. Only the filters that aren't already in use are set.
+    pub fn push_down(&self, filters: &mut KernelFilters) {
+        let mut conjuncts = vec![&self.0];
+        while let Some(node) = conjuncts.pop() {
+            match node {
+                Node::And(left, right) => conjuncts.extend([left.as_ref(), right.as_ref()]),
+                node => {
+                    // Only one set of values can be pushed down per field, so the first one wins.
+                    if let Some(values) = node.kernel_values() {
+                        values.push_down(filters);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl FilterExpression {
+    /// An expression that matches the events the eBPF programs would let through given some filters, for when events
+    /// don't come from them, e.g. when replaying a capture. Following children isn't supported given that needs the
+    /// processes to be traced as they're created.
+    pub fn from_kernel_filters(filters: &KernelFilters) -> Option<Self> {
+        let equal = |field, value| Node::Comparison { field, operator: Operator::Equal, value };
+        let groups = [
+            filters.pids.iter().map(|pid| equal(Field::Pid, Value::Integer((*pid).into()))).collect::<Vec<_>>(),
+            filters
+                .commands
+                .iter()
+                .map(|command| {
+                    // Process names are truncated by the kernel, and so are the ones in the filter.
+                    let length = command.len().min(COMMAND_MAX);
+                    let command = String::from_utf8_lossy(&command.as_bytes()[..length]).into_owned();
+                    equal(Field::Command, Value::Text(command))
+                })
+                .collect(),
+            filters.ports.iter().map(|port| equal(Field::Port, Value::Integer((*port).into()))).collect(),
+            filters.networks.iter().map(|network| equal(Field::Address, Value::Network(network.clone()))).collect(),
+        ];
+        let node = groups
+            .into_iter()
+            .filter_map(|group| group.into_iter().reduce(|left, right| Node::Or(Box::new(left), Box::new(right))))
+            .reduce(|left, right| Node::And(Box::new(left), Box::new(right)))?;
+        Some(Self(node))
+    }
+
+    /// An expression that matches the events that match both this one and another one.
+    pub fn and(self, other: Self) -> Self {
+        Self(Node::And(Box::new(self.0), Box::new(other.0)))
+    }
+}
+
+impl FromStr for FilterExpression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = tokenize(s)?.into_iter().peekable();
+        let node = parse_or(&mut tokens)?;
+        if let Some(token) = tokens.next() {
+            bail!("unexpected {token}");
+        }
+        Ok(Self(node))
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Comparison { field: Field, operator: Operator, value: Value },
+}
+
+impl Node {
+    fn matches(&self, fields: &EventFields) -> bool {
+        match self {
+            Self::And(left, right) => left.matches(fields) && right.matches(fields),
+            Self::Or(left, right) => left.matches(fields) || right.matches(fields),
+            Self::Not(node) => !node.matches(fields),
+            Self::Comparison { field, operator, value } => {
+                let values = field.values(fields);
+                match operator {
+                    Operator::NotEqual => !values.iter().any(|actual| Operator::Equal.compare(actual, value)),
+                    Operator::NotMatches => !values.iter().any(|actual| Operator::Matches.compare(actual, value)),
+                    operator => values.iter().any(|actual| operator.compare(actual, value)),
+                }
+            }
+        }
+    }
+
+    /// The values this node requires a field to have, if it's something the eBPF programs can filter on.
+    fn kernel_values(&self) -> Option<KernelValues> {
+        match self {
+            Self::Or(left, right) => left.kernel_values()?.union(right.kernel_values()?),
+            Self::Comparison { field, operator: Operator::Equal, value } => match (field, value) {
+                (Field::Pid, Value::Integer(pid)) => Some(KernelValues::Pids(vec![u32::try_from(*pid).ok()?])),
+                (Field::Command, Value::Text(command)) => Some(KernelValues::Commands(vec![command.clone()])),
+                (Field::Port, Value::Integer(port)) => Some(KernelValues::Ports(vec![u16::try_from(*port).ok()?])),
+                (Field::Address, Value::Network(network)) => Some(KernelValues::Networks(vec![network.clone()])),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// The values a field must have for an event to match, for the fields the eBPF programs can filter on.
+enum KernelValues {
+    Pids(Vec<u32>),
+    Commands(Vec<String>),
+    Ports(Vec<u16>),
+    Networks(Vec<Network>),
+}
+
+impl KernelValues {
+    fn union(self, other: Self) -> Option<Self> {
+        use KernelValues::*;
+        match (self, other) {
+            (Pids(mut left), Pids(right)) => {
+                left.extend(right);
+                Some(Pids(left))
+            }
+            (Commands(mut left), Commands(right)) => {
+                left.extend(right);
+                Some(Commands(left))
+            }
+            (Ports(mut left), Ports(right)) => {
+                left.extend(right);
+                Some(Ports(left))
+            }
+            (Networks(mut left), Networks(right)) => {
+                left.extend(right);
+                Some(Networks(left))
+            }
+            _ => None,
+        }
+    }
+
+    fn push_down(self, filters: &mut KernelFilters) {
+        // Filters that are already in use were given explicitly, which takes precedence.
+        let flags = filters.flags();
+        let unused = |filter| flags & filter == 0;
+        // The eBPF programs require the port and the network to match on the same endpoint, while the expression lets
+        // them match on different ones, e.g. a local port and a remote address. So only one of them is pushed down.
+        let addressing_unused = unused(FILTER_PORT | FILTER_NETWORK);
+        match self {
+            Self::Pids(pids) if unused(FILTER_PID) => filters.pids = pids,
+            Self::Commands(commands) if unused(FILTER_COMMAND) => filters.commands = commands,
+            Self::Ports(ports) if addressing_unused => filters.ports = ports,
+            Self::Networks(networks) if addressing_unused => filters.networks = networks,
+            _ => (),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Pid,
+    Tid,
+    Uid,
+    Gid,
+    Fd,
+    Command,
+    Syscall,
+    ReturnValue,
+    Errno,
+    Family,
+    Port,
+    Address,
+    Path,
+    State,
+}
+
+/// The kind of values a field holds, which determines the comparisons that can be made on it.
+#[derive(PartialEq, Eq)]
+enum FieldType {
+    Integer,
+    Text,
+    Address,
+}
+
+impl Field {
+    fn parse(name: &str) -> anyhow::Result<Self> {
+        use Field::*;
+        let field = match name {
+            "pid" => Pid,
+            "tid" => Tid,
+            "uid" => Uid,
+            "gid" => Gid,
+            "fd" => Fd,
+            "comm" => Command,
+            "syscall" => Syscall,
+            "ret" => ReturnValue,
+            "errno" => Errno,
+            "family" => Family,
+            "port" => Port,
+            "addr" => Address,
+            "path" => Path,
+            "state" => State,
+            _ => bail!("unknown field '{name}'"),
+        };
+        Ok(field)
+    }
+
+    fn field_type(&self) -> FieldType {
+        use Field::*;
+        match self {
+            Pid | Tid | Uid | Gid | Fd | ReturnValue | Port => FieldType::Integer,
+            Command | Syscall | Errno | Family | Path | State => FieldType::Text,
+            Address => FieldType::Address,
+        }
+    }
+
+    /// Converts a value into the form it's compared in. Names of things, like syscalls, are compared ignoring case
+    /// and underscores, so `getsockname` is the same as `get_sock_name`.
+    fn normalize(&self, value: &str) -> String {
+        match self {
+            Self::Syscall | Self::Errno | Self::Family | Self::State => {
+                value.chars().filter(|c| *c != '_').flat_map(char::to_lowercase).collect()
+            }
+            _ => value.to_string(),
+        }
+    }
+
+    fn values(&self, fields: &EventFields) -> Vec<Value> {
+        let value = match self {
+            Self::Pid => Some(Value::Integer(fields.pid.into())),
+            Self::Tid => fields.tid.map(|tid| Value::Integer(tid.into())),
+            Self::Uid => fields.uid.map(|uid| Value::Integer(uid.into())),
+            Self::Gid => fields.gid.map(|gid| Value::Integer(gid.into())),
+            Self::Fd => fields.fd.map(|fd| Value::Integer(fd.into())),
+            Self::Command => Some(Value::Text(parse_command(&fields.command))),
+            Self::Syscall => fields.syscall.map(|syscall| Value::Text(self.normalize(syscall))),
+            Self::ReturnValue => fields.return_value.map(Value::Integer),
+            Self::Errno => fields
+                .return_value
+                .and_then(|value| crate::errno::Errno::from_i64(-value))
+                .map(|errno| Value::Text(self.normalize(&format!("{errno:?}")))),
+            Self::Family => fields.family.map(|family| Value::Text(self.normalize(&format!("{family:?}")))),
+            Self::Port => return fields.endpoints.iter().map(|(_, port)| Value::Integer((*port).into())).collect(),
+            Self::Address => return fields.endpoints.iter().map(|(address, _)| Value::Address(*address)).collect(),
+            Self::Path => fields.path.clone().map(Value::Text),
+            Self::State => fields.state.as_ref().map(|state| Value::Text(self.normalize(&format!("{state:?}")))),
+        };
+        value.into_iter().collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Matches,
+    NotMatches,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Operator {
+    fn parse(operator: &str) -> Option<Self> {
+        use Operator::*;
+        let operator = match operator {
+            "==" => Equal,
+            "!=" => NotEqual,
+            "~" => Matches,
+            "!~" => NotMatches,
+            "<" => Less,
+            "<=" => LessOrEqual,
+            ">" => Greater,
+            ">=" => GreaterOrEqual,
+            _ => return None,
+        };
+        Some(operator)
+    }
+
+    fn supports(&self, field_type: &FieldType) -> bool {
+        use Operator::*;
+        match self {
+            Equal | NotEqual => true,
+            Matches | NotMatches => *field_type == FieldType::Text,
+            Less | LessOrEqual | Greater | GreaterOrEqual => *field_type == FieldType::Integer,
+        }
+    }
+
+    /// Compares the value in an event against the one in an expression. Negated operators are handled by the caller.
+    fn compare(&self, actual: &Value, expected: &Value) -> bool {
+        use Operator::*;
+        match (self, actual, expected) {
+            (Equal, Value::Integer(actual), Value::Integer(expected)) => actual == expected,
+            (Less, Value::Integer(actual), Value::Integer(expected)) => actual < expected,
+            (LessOrEqual, Value::Integer(actual), Value::Integer(expected)) => actual <= expected,
+            (Greater, Value::Integer(actual), Value::Integer(expected)) => actual > expected,
+            (GreaterOrEqual, Value::Integer(actual), Value::Integer(expected)) => actual >= expected,
+            (Equal, Value::Text(actual), Value::Text(expected)) => actual == expected,
+            (Matches, Value::Text(actual), Value::Pattern(pattern)) => pattern.is_match(actual),
+            (Equal, Value::Address(actual), Value::Network(network)) => network.contains(actual),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Integer(i64),
+    Text(String),
+    Address(IpAddr),
+    Pattern(Regex),
+    Network(Network),
+}
+
+impl Value {
+    fn parse(field: &Field, operator: &Operator, token: Token) -> anyhow::Result<Self> {
+        let text = match token {
+            Token::Word(text) | Token::Quoted(text) => text,
+            token => bail!("expected a value, found {token}"),
+        };
+        if let Operator::Matches | Operator::NotMatches = operator {
+            return Ok(Self::Pattern(Regex::new(&text).context("invalid regular expression")?));
+        }
+        let value = match field {
+            Field::Errno => {
+                let errno = Errno::from_name(&text).ok_or_else(|| anyhow!("unknown errno '{text}'"))?;
+                Self::Text(field.normalize(&format!("{errno:?}")))
+            }
+            Field::State => {
+                let state = (1..=12)
+                    .filter_map(TcpState::from_u32)
+                    .find(|state| field.normalize(&format!("{state:?}")) == field.normalize(&text))
+                    .ok_or_else(|| anyhow!("unknown TCP state '{text}'"))?;
+                Self::Text(field.normalize(&format!("{state:?}")))
+            }
+            Field::Address => Self::Network(text.parse().with_context(|| format!("invalid network '{text}'"))?),
+            field if field.field_type() == FieldType::Integer => {
+                Self::Integer(text.parse().with_context(|| format!("invalid number '{text}'"))?)
+            }
+            field => Self::Text(field.normalize(&text)),
+        };
+        Ok(value)
+    }
+}
+
+/// The fields shared by all kinds of events, so each field is only extracted in one place, and only once per event.
+#[derive(Default)]
+struct EventFields {
+    pid: u32,
+    tid: Option<u32>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    fd: Option<u32>,
+    command: [u8; 16],
+    syscall: Option<&'static str>,
+    return_value: Option<i64>,
+    family: Option<AddressFamily>,
+    endpoints: Vec<(IpAddr, u16)>,
+    path: Option<String>,
+    state: Option<TcpState>,
+}
+
+impl EventFields {
+    fn new(event: &BpfEvent) -> Self {
+        match event {
+            BpfEvent::Sockaddr(event) => {
+                let mut endpoints = Vec::new();
+                let mut path = None;
+                match event.family {
+                    AddressFamily::Unix => {
+                        let length = (event.path_length as usize).min(event.path.len());
+                        path = Some(unix_path(&event.path[..length]));
+                    }
+                    family => {
+                        if let Ok(address) = parse_address(&family, &event.address) {
+                            endpoints.push((address, event.port.to_be()));
+                        }
+                        if event.implicit_peer {
+                            if let Ok(address) = parse_address(&family, &event.local_address) {
+                                endpoints.push((address, event.local_port.to_be()));
+                            }
+                        }
+                    }
+                };
+                Self {
+                    pid: event.pid,
+                    tid: Some(event.tid),
+                    uid: Some(event.uid),
+                    gid: Some(event.gid),
+                    fd: Some(event.fd),
+                    command: event.command,
+                    syscall: Some(syscall_name(&event.syscall)),
+                    return_value: Some(event.return_value),
+                    family: Some(event.family),
+                    endpoints,
+                    path,
+                    state: None,
+                }
+            }
+            BpfEvent::SocketState(event) => {
+                let mut endpoints = Vec::new();
+                if let Ok(address) = parse_address(&event.family, &event.src_address) {
+                    endpoints.push((address, event.src_port));
+                }
+                if let Ok(address) = parse_address(&event.family, &event.dst_address) {
+                    endpoints.push((address, event.dst_port));
+                }
+                Self {
+                    pid: event.pid,
+                    tid: Some(event.tid),
+                    uid: Some(event.uid),
+                    gid: Some(event.gid),
+                    command: event.command,
+                    family: Some(event.family),
+                    endpoints,
+                    state: TcpState::from_u32(event.new_state),
+                    ..Default::default()
+                }
+            }
+            BpfEvent::SocketCreated(event) => Self {
+                pid: event.pid,
+                tid: Some(event.tid),
+                uid: Some(event.uid),
+                gid: Some(event.gid),
+                command: event.command,
+                syscall: Some("socket"),
+                return_value: Some(event.return_value),
+                ..Default::default()
+            },
+            BpfEvent::SocketCall(event) => Self {
+                pid: event.pid,
+                tid: Some(event.tid),
+                uid: Some(event.uid),
+                gid: Some(event.gid),
+                fd: Some(event.fd),
+                command: event.command,
+                syscall: Some(syscall_name(&event.syscall)),
+                return_value: Some(event.return_value),
+                ..Default::default()
+            },
+            BpfEvent::SocketOption(event) => Self {
+                pid: event.pid,
+                tid: Some(event.tid),
+                uid: Some(event.uid),
+                gid: Some(event.gid),
+                fd: Some(event.fd),
+                command: event.command,
+                syscall: Some("set_sock_opt"),
+                return_value: Some(event.return_value),
+                ..Default::default()
+            },
+            BpfEvent::ProcessExec(event) => Self { pid: event.pid, command: event.command, ..Default::default() },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Operator(String),
+    OpenParen,
+    CloseParen,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Word(word) => write!(f, "'{word}'"),
+            Self::Quoted(text) => write!(f, "{text:?}"),
+            Self::Operator(operator) => write!(f, "'{operator}'"),
+            Self::OpenParen => write!(f, "'('"),
+            Self::CloseParen => write!(f, "')'"),
+        }
+    }
+}
+
+fn tokenize(expression: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => (),
+            '(' => tokens.push(Token::OpenParen),
+            ')' => tokens.push(Token::CloseParen),
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => text.extend(chars.next()),
+                        Some(c) => text.push(c),
+                        None => bail!("unterminated string"),
+                    }
+                }
+                tokens.push(Token::Quoted(text));
+            }
+            '=' | '!' | '~' | '<' | '>' | '&' | '|' => {
+                let mut operator = c.to_string();
+                if let Some(next) = chars
+                    .next_if(|next| matches!((c, next), ('=' | '!' | '<' | '>', '=' | '~') | ('&', '&') | ('|', '|')))
+                {
+                    operator.push(next);
+                }
+                tokens.push(Token::Operator(operator));
+            }
+            c if is_word_char(c) => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| is_word_char(*c)) {
+                    word.push(c);
+                }
+                tokens.push(Token::Word(word));
+            }
+            c => bail!("unexpected character '{c}'"),
+        }
+    }
+    Ok(tokens)
+}
+
+// Words include the characters used in addresses and networks, e.g. `10.0.0.0/8` or `::1`, and negative numbers.
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '.' | ':' | '/' | '-')
+}
+
+type Tokens = Peekable<IntoIter<Token>>;
+
+fn parse_or(tokens: &mut Tokens) -> anyhow::Result<Node> {
+    let mut node = parse_and(tokens)?;
+    while consume_keyword(tokens, "or", "||") {
+        node = Node::Or(Box::new(node), Box::new(parse_and(tokens)?));
+    }
+    Ok(node)
+}
+
+fn parse_and(tokens: &mut Tokens) -> anyhow::Result<Node> {
+    let mut node = parse_unary(tokens)?;
+    while consume_keyword(tokens, "and", "&&") {
+        node = Node::And(Box::new(node), Box::new(parse_unary(tokens)?));
+    }
+    Ok(node)
+}
+
+fn parse_unary(tokens: &mut Tokens) -> anyhow::Result<Node> {
+    if consume_keyword(tokens, "not", "!") {
+        return Ok(Node::Not(Box::new(parse_unary(tokens)?)));
+    }
+    match tokens.next() {
+        Some(Token::OpenParen) => {
+            let node = parse_or(tokens)?;
+            match tokens.next() {
+                Some(Token::CloseParen) => Ok(node),
+                Some(token) => bail!("expected ')', found {token}"),
+                None => bail!("expected ')'"),
+            }
+        }
+        Some(Token::Word(name)) => {
+            let field = Field::parse(&name)?;
+            let symbol = match tokens.next() {
+                Some(Token::Operator(symbol)) => symbol,
+                Some(token) => bail!("expected an operator after '{name}', found {token}"),
+                None => bail!("expected an operator after '{name}'"),
+            };
+            let operator = Operator::parse(&symbol).ok_or_else(|| anyhow!("unknown operator '{symbol}'"))?;
+            if !operator.supports(&field.field_type()) {
+                bail!("'{name}' can't be compared using '{symbol}'");
+            }
+            let value = tokens.next().ok_or_else(|| anyhow!("expected a value after '{name}'"))?;
+            let value = Value::parse(&field, &operator, value)?;
+            Ok(Node::Comparison { field, operator, value })
+        }
+        Some(token) => bail!("expected a comparison, found {token}"),
+        None => bail!("expected a comparison"),
+    }
+}
+
+fn consume_keyword(tokens: &mut Tokens, keyword: &str, operator: &str) -> bool {
+    let found = match tokens.peek() {
+        Some(Token::Word(word)) => word == keyword,
+        Some(Token::Operator(symbol)) => symbol == operator,
+        _ => false,
+    };
+    if found {
+        tokens.next();
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sockwho_common::{SocketCallEvent, Syscall};
+
+    fn listen(pid: u32, command: &str) -> BpfEvent {
+        let mut name = [0; 16];
+        name[..command.len()].copy_from_slice(command.as_bytes());
+        BpfEvent::SocketCall(SocketCallEvent {
+            pid,
+            fd: 3,
+            syscall: Syscall::Listen,
+            _padding: [0; 3],
+            tid: pid,
+            uid: 1000,
+            gid: 1000,
+            ppid: 1,
+            net_namespace: 0,
+            argument: 128,
+            command: name,
+            return_value: 0,
+            timestamp: 0,
+            duration: 0,
+            cgroup_id: 0,
+            start_time: 0,
+        })
+    }
+
+    fn matches(expression: &str, event: &BpfEvent) -> bool {
+        expression.parse::<FilterExpression>().expect("invalid expression").matches(event)
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let expression = "pid == 1 or pid == 2 and comm == nginx";
+        assert!(matches(expression, &listen(1, "curl")));
+        assert!(matches(expression, &listen(2, "nginx")));
+        assert!(!matches(expression, &listen(2, "curl")));
+        assert!(matches("pid == 1 || pid == 2 && comm == nginx", &listen(1, "curl")));
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and() {
+        assert!(matches("not pid == 1 and comm == curl", &listen(2, "curl")));
+        assert!(!matches("not pid == 1 and comm == curl", &listen(1, "curl")));
+        assert!(!matches("!(pid == 1 || pid == 2)", &listen(2, "curl")));
+        assert!(matches("not not pid == 1", &listen(1, "curl")));
+    }
+
+    #[test]
+    fn negated_comparisons_on_missing_fields_match() {
+        // Calls to listen(2) have no address, so none of the comparisons on it hold.
+        let event = listen(1, "nginx");
+        assert!(!matches("port == 80", &event));
+        assert!(matches("port != 80", &event));
+        assert!(!matches("path ~ sock", &event));
+        assert!(matches("path !~ sock", &event));
+        assert!(!matches("not port != 80", &event));
+    }
+
+    fn push_down(expression: &str) -> KernelFilters {
+        let mut filters = KernelFilters::default();
+        expression.parse::<FilterExpression>().expect("invalid expression").push_down(&mut filters);
+        filters
+    }
+
+    #[test]
+    fn push_down_uses_top_level_conjuncts() {
+        let filters = push_down("(pid == 1 or pid == 2) and comm == curl and port == 443");
+        assert_eq!(filters.pids, [1, 2]);
+        assert_eq!(filters.commands, ["curl"]);
+        assert_eq!(filters.ports, [443]);
+    }
+
+    #[test]
+    fn push_down_ignores_nested_conjuncts() {
+        let filters = push_down("pid == 1 or (pid == 2 and port == 443)");
+        assert!(filters.pids.is_empty());
+        assert!(filters.ports.is_empty());
+        let filters = push_down("pid == 1 or port == 443");
+        assert!(filters.pids.is_empty());
+        assert!(filters.ports.is_empty());
+        let filters = push_down("not pid == 1");
+        assert!(filters.pids.is_empty());
+    }
+
+    #[test]
+    fn push_down_keeps_explicit_filters() {
+        let mut filters = KernelFilters { pids: vec![5], ..Default::default() };
+        "pid == 1 and port == 443".parse::<FilterExpression>().expect("invalid expression").push_down(&mut filters);
+        assert_eq!(filters.pids, [5]);
+        assert_eq!(filters.ports, [443]);
+    }
+
+    #[test]
+    fn push_down_only_uses_one_addressing_filter() {
+        // The port could be the local one and the address the remote one, which the eBPF programs can't tell apart.
+        let filters = push_down("port == 443 and addr == 10.0.0.0/8");
+        assert_eq!(filters.ports.len() + filters.networks.len(), 1);
+        let mut filters = KernelFilters { ports: vec![80], ..Default::default() };
+        "addr == 10.0.0.0/8".parse::<FilterExpression>().expect("invalid expression").push_down(&mut filters);
+        assert_eq!(filters.ports, [80]);
+        assert!(filters.networks.is_empty());
+    }
+}
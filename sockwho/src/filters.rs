@@ -0,0 +1,224 @@
+use anyhow::{anyhow, Context};
+use aya::{
+    maps::{
+        lpm_trie::{Key, LpmTrie},
+        HashMap,
+    },
+    Bpf,
+};
+use log::warn;
+use sockwho_common::{FILTER_COMMAND, FILTER_FOLLOW_CHILDREN, FILTER_NETWORK, FILTER_PID, FILTER_PORT};
+use std::{fs, net::IpAddr, str::FromStr};
+
+// Process names are truncated to this many bytes by the kernel, not counting the NUL terminator.
+pub(crate) const COMMAND_MAX: usize = 15;
+
+/// The filters applied by the eBPF programs, so the events that don't match them never reach userspace.
+///
+/// An event has to match every filter that's in use, and any of the values given for each of them.
+#[derive(Clone, Debug, Default)]
+pub struct KernelFilters {
+    pub pids: Vec<u32>,
+    pub commands: Vec<String>,
+    pub ports: Vec<u16>,
+    pub networks: Vec<Network>,
+
+    /// Whether the descendants of the processes in `pids` are traced as well, including the ones created by the ones
+    /// added after loading the programs via `add_pid`.
+    pub follow_children: bool,
+}
+
+impl KernelFilters {
+    /// The combination of the `FILTER_*` flags for the filters that are in use.
+    pub fn flags(&self) -> u8 {
+        let mut flags = 0;
+        if !self.pids.is_empty() || self.follow_children {
+            flags |= FILTER_PID;
+        }
+        if self.follow_children {
+            flags |= FILTER_FOLLOW_CHILDREN;
+        }
+        if !self.commands.is_empty() {
+            flags |= FILTER_COMMAND;
+        }
+        if !self.ports.is_empty() {
+            flags |= FILTER_PORT;
+        }
+        if !self.networks.is_empty() {
+            flags |= FILTER_NETWORK;
+        }
+        flags
+    }
+
+    /// Stores the filters in the maps the eBPF programs look them up in.
+    pub fn populate(&self, bpf: &Bpf) -> anyhow::Result<()> {
+        for pid in &self.pids {
+            Self::add_pid(bpf, *pid)?;
+        }
+        if self.follow_children {
+            // The ones created from now on are added by the eBPF programs as they're forked.
+            let descendants = self.pids.iter().flat_map(|pid| descendants(*pid));
+            for pid in descendants {
+                if let Err(e) = Self::add_pid(bpf, pid) {
+                    warn!("Not tracing every descendant of the given processes: {e:#}");
+                    break;
+                }
+            }
+        }
+        let mut commands = HashMap::<_, [u8; 16], u8>::try_from(bpf.map_mut("FILTER_COMMANDS")?)?;
+        for command in &self.commands {
+            let mut key = [0; 16];
+            let length = command.len().min(COMMAND_MAX);
+            key[..length].copy_from_slice(&command.as_bytes()[..length]);
+            commands.insert(key, 1, 0).context("adding process name filter")?;
+        }
+        let mut ports = HashMap::<_, u16, u8>::try_from(bpf.map_mut("FILTER_PORTS")?)?;
+        for port in &self.ports {
+            ports.insert(*port, 1, 0).context("adding port filter")?;
+        }
+        let networks = LpmTrie::<_, [u8; 16], u8>::try_from(bpf.map_mut("FILTER_NETWORKS")?)?;
+        for network in &self.networks {
+            networks.insert(&network.key(), 1, 0).context("adding network filter")?;
+        }
+        Ok(())
+    }
+
+    /// Adds a process to the pid filter once the programs are loaded.
+    pub fn add_pid(bpf: &Bpf, pid: u32) -> anyhow::Result<()> {
+        let mut pids = HashMap::<_, u32, u8>::try_from(bpf.map_mut("FILTER_PIDS")?)?;
+        pids.insert(pid, 1, 0).context("adding pid filter")?;
+        Ok(())
+    }
+}
+
+/// The processes that descend from one that are already running, as listed in `/proc/<pid>/task/<tid>/children` for
+/// each of their threads. Processes that exit while this runs are skipped.
+fn descendants(pid: u32) -> Vec<u32> {
+    let mut descendants = Vec::new();
+    let mut pending = vec![pid];
+    while let Some(pid) = pending.pop() {
+        let Ok(threads) = fs::read_dir(format!("/proc/{pid}/task")) else {
+            continue;
+        };
+        for thread in threads.flatten() {
+            let Ok(children) = fs::read_to_string(thread.path().join("children")) else {
+                continue;
+            };
+            for child in children.split_whitespace().filter_map(|child| child.parse().ok()) {
+                descendants.push(child);
+                pending.push(child);
+            }
+        }
+    }
+    descendants
+}
+
+/// An IP network in CIDR notation, e.g. `10.0.0.0/8`. An address on its own is a network that only contains itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Network {
+    address: IpAddr,
+    prefix_length: u8,
+}
+
+impl Network {
+    /// Whether an address belongs to this network.
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        let (network, prefix_length) = self.mapped();
+        let mask = u128::MAX.checked_shl(128 - prefix_length).unwrap_or(0);
+        u128::from_be_bytes(network) & mask == u128::from_be_bytes(mapped_address(address)) & mask
+    }
+
+    /// The key used for this network in the eBPF programs' LPM trie.
+    fn key(&self) -> Key<[u8; 16]> {
+        let (network, prefix_length) = self.mapped();
+        Key::new(prefix_length, network)
+    }
+
+    /// This network as an IPv6 one, where IPv4 networks are turned into IPv4-mapped IPv6 ones.
+    fn mapped(&self) -> ([u8; 16], u32) {
+        let prefix_length = match self.address {
+            IpAddr::V4(_) => 96 + self.prefix_length as u32,
+            IpAddr::V6(_) => self.prefix_length as u32,
+        };
+        (mapped_address(&self.address), prefix_length)
+    }
+}
+
+impl FromStr for Network {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_length) = match s.split_once('/') {
+            Some((address, prefix_length)) => (address, Some(prefix_length)),
+            None => (s, None),
+        };
+        let address: IpAddr = address.parse().context("invalid address")?;
+        let max_length = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_length = match prefix_length {
+            Some(prefix_length) => prefix_length.parse().context("invalid prefix length")?,
+            None => max_length,
+        };
+        if prefix_length > max_length {
+            return Err(anyhow!("prefix length can't be larger than {max_length}"));
+        }
+        Ok(Self { address, prefix_length })
+    }
+}
+
+fn mapped_address(address: &IpAddr) -> [u8; 16] {
+    match address {
+        IpAddr::V4(address) => address.to_ipv6_mapped().octets(),
+        IpAddr::V6(address) => address.octets(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(text: &str) -> Network {
+        text.parse().expect("invalid network")
+    }
+
+    fn contains(network_text: &str, address: &str) -> bool {
+        network(network_text).contains(&address.parse().unwrap())
+    }
+
+    #[test]
+    fn parses_networks_and_plain_addresses() {
+        assert_eq!(network("10.0.0.0/8").prefix_length, 8);
+        assert_eq!(network("10.1.2.3").prefix_length, 32);
+        assert_eq!(network("fd00::/8").prefix_length, 8);
+        assert_eq!(network("::1").prefix_length, 128);
+        assert_eq!(network("0.0.0.0/0").prefix_length, 0);
+    }
+
+    #[test]
+    fn rejects_invalid_networks() {
+        for text in ["10.0.0.0/33", "fd00::/129", "10.0.0.0/", "10.0.0.0/-1", "10.0.0.0/8/8", "10.0.0/8", "nginx"] {
+            assert!(text.parse::<Network>().is_err(), "{text} should be rejected");
+        }
+    }
+
+    #[test]
+    fn contains_addresses_in_the_network() {
+        assert!(contains("10.0.0.0/8", "10.255.0.1"));
+        assert!(!contains("10.0.0.0/8", "11.0.0.1"));
+        assert!(contains("192.168.1.0/24", "192.168.1.255"));
+        assert!(!contains("192.168.1.0/24", "192.168.2.0"));
+        assert!(contains("10.1.2.3", "10.1.2.3"));
+        assert!(!contains("10.1.2.3", "10.1.2.4"));
+        assert!(contains("0.0.0.0/0", "203.0.113.7"));
+        assert!(contains("fd00::/8", "fd12::1"));
+        assert!(!contains("fd00::/8", "fe80::1"));
+        assert!(contains("::/0", "2001:db8::1"));
+    }
+
+    #[test]
+    fn ipv4_networks_contain_ipv4_mapped_addresses_only() {
+        assert!(contains("10.0.0.0/8", "::ffff:10.0.0.1"));
+        assert!(!contains("10.0.0.0/8", "a00::1"));
+        // An IPv4 network covering every address still only covers IPv4 ones.
+        assert!(!contains("0.0.0.0/0", "2001:db8::1"));
+    }
+}
@@ -0,0 +1,482 @@
+use crate::{
+    event::{Identity, StateChangeEvent},
+    processor::TcpState,
+};
+use serde::Serialize;
+use sockwho_common::AddressFamily;
+use std::{collections::HashMap, fmt, net::IpAddr};
+
+// How long a connection that was closed before the connect(2) call that opened it returned, e.g. because it was
+// refused, is kept around waiting for that call.
+const CONNECT_GRACE_PERIOD: u64 = 1_000_000_000;
+
+// How long a connection is kept without its state changing before it's assumed the state change that closed it was
+// missed, e.g. because events were lost. Established connections can legitimately stay that way for much longer than
+// connections in any other state.
+const IDLE_TIMEOUT: u64 = 5 * 60 * 1_000_000_000;
+const ESTABLISHED_IDLE_TIMEOUT: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+// How often connections are checked for being idle, given that means going through all of them.
+const IDLE_CHECK_INTERVAL: u64 = 10 * 1_000_000_000;
+
+/// The endpoints of a TCP connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FlowTuple {
+    pub local_address: IpAddr,
+    pub local_port: u16,
+    pub remote_address: IpAddr,
+    pub remote_port: u16,
+}
+
+/// Which side opened a connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// The connection was opened via connect(2).
+    Outbound,
+
+    /// The connection was opened by the peer and accepted by a listening socket.
+    Inbound,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Outbound => write!(f, "outbound"),
+            Self::Inbound => write!(f, "inbound"),
+        }
+    }
+}
+
+/// Why a connection was closed, as told from the state changes it went through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    /// The connection was never established, e.g. because it was refused or timed out.
+    Failed,
+
+    /// The connection was reset, or aborted, while established.
+    Reset,
+
+    /// Our side closed the connection first.
+    LocalClose,
+
+    /// The peer closed the connection first.
+    RemoteClose,
+
+    /// The connection was already being closed when it was first seen.
+    Unknown,
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            Self::Failed => "failed",
+            Self::Reset => "reset",
+            Self::LocalClose => "local_close",
+            Self::RemoteClose => "remote_close",
+            Self::Unknown => "unknown",
+        };
+        write!(f, "{reason}")
+    }
+}
+
+/// The process that owns a connection.
+#[derive(Clone, Debug)]
+pub struct FlowOwner {
+    pub pid: u32,
+    pub start_time: u64,
+    pub command: String,
+    pub identity: Identity,
+}
+
+/// A TCP connection, from the time it's opened until it's closed.
+#[derive(Clone, Debug)]
+pub struct Flow {
+    pub tuple: FlowTuple,
+    /// The inode of the network namespace the connection is in, as recorded by the kernel, or 0 if it isn't known.
+    pub net_namespace: u32,
+    pub family: AddressFamily,
+    pub direction: Direction,
+    pub owner: FlowOwner,
+    pub fd: Option<u32>,
+
+    /// The value connect(2) returned, for outbound connections.
+    pub connect_result: Option<i64>,
+
+    /// The times at which the connection was first seen, established and closed.
+    pub opened: u64,
+    pub established: Option<u64>,
+    pub closed: Option<u64>,
+
+    // Only set if the connection was first seen when its handshake started.
+    handshake_started: Option<u64>,
+    // The time of the latest state change.
+    last_changed: u64,
+
+    pub close_reason: CloseReason,
+
+    /// Whether any of the events that make up this connection matched the filter.
+    pub visible: bool,
+}
+
+impl Flow {
+    /// How long the handshake took, if the connection was seen from the start of it until it was established.
+    pub fn handshake(&self) -> Option<u64> {
+        Some(self.established?.saturating_sub(self.handshake_started?))
+    }
+}
+
+/// Correlates socket state changes with the connect(2) and accept(2) calls behind them into connections.
+///
+/// State changes that happen in softirq context, e.g. a connection being established, are attributed to whatever
+/// process was running, so connections are owned by the process that called connect(2) or accept(2).
+#[derive(Default)]
+pub struct FlowTracker {
+    flows: HashMap<u64, Flow>,
+    next_id: u64,
+    // The open connections with these endpoints. Outbound connections are first seen before they're assigned a local
+    // port, so they're found using port 0 until then.
+    tuples: HashMap<FlowTuple, Vec<u64>>,
+    // Outbound connections waiting for the connect(2) call that opened them to return, by pid and remote endpoint.
+    connecting: HashMap<(u32, IpAddr, u16), u64>,
+    // Inbound connections waiting to be accepted, by network namespace and remote endpoint.
+    accepting: HashMap<(u32, IpAddr, u16), u64>,
+    // When connections are next checked for being idle.
+    next_idle_check: u64,
+}
+
+impl FlowTracker {
+    /// Records a TCP socket state change in the given network namespace, returning the connection if it's now finished.
+    pub fn state_changed(
+        &mut self,
+        change: &StateChangeEvent,
+        net_namespace: u32,
+        timestamp: u64,
+        owner: FlowOwner,
+        visible: bool,
+    ) -> Option<Flow> {
+        let StateChangeEvent { family, old_state, new_state, .. } = change;
+        // Listening sockets aren't connections.
+        if *old_state == TcpState::Listen || *new_state == TcpState::Listen {
+            return None;
+        }
+        let tuple = FlowTuple {
+            local_address: change.source_address,
+            local_port: change.source_port,
+            remote_address: change.destination_address,
+            remote_port: change.destination_port,
+        };
+        let id = match self.find(&tuple, net_namespace) {
+            Some(id) => id,
+            None if *new_state == TcpState::Close => return None,
+            None => self.open(tuple, net_namespace, *family, new_state, timestamp, owner),
+        };
+        let flow = self.flows.get_mut(&id)?;
+        flow.visible |= visible;
+        flow.last_changed = timestamp;
+        use TcpState::*;
+        match (old_state, new_state) {
+            (_, Established) => flow.established = Some(timestamp),
+            (SynSent | SynReceived | NewSynReceived, Close) => flow.close_reason = CloseReason::Failed,
+            (Established, Close) => flow.close_reason = CloseReason::Reset,
+            (Established, FinWait1) => flow.close_reason = CloseReason::LocalClose,
+            (Established, CloseWait) => flow.close_reason = CloseReason::RemoteClose,
+            _ => (),
+        };
+        if *new_state != Close {
+            return None;
+        }
+        flow.closed = Some(timestamp);
+        let (tuple, direction, fd, connect_result) = (flow.tuple, flow.direction, flow.fd, flow.connect_result);
+        self.forget_tuple(&tuple, id);
+        if direction == Direction::Inbound && fd.is_none() {
+            self.accepting.retain(|_, pending| *pending != id);
+        }
+        // Refused connections can be closed before connect(2) returns.
+        if direction == Direction::Outbound && connect_result.is_none() && self.connecting.values().any(|i| *i == id) {
+            return None;
+        }
+        self.flows.remove(&id)
+    }
+
+    /// Records a call to connect(2) returning, returning the connection if it was already closed.
+    pub fn connected(
+        &mut self,
+        pid: u32,
+        address: IpAddr,
+        port: u16,
+        fd: u32,
+        return_value: i64,
+        visible: bool,
+    ) -> Option<Flow> {
+        let id = self.connecting.remove(&(pid, address, port))?;
+        let flow = self.flows.get_mut(&id)?;
+        flow.fd = Some(fd);
+        flow.connect_result = Some(return_value);
+        flow.visible |= visible;
+        match flow.closed {
+            Some(_) => self.flows.remove(&id),
+            None => None,
+        }
+    }
+
+    /// Records a connection being accepted by a process in the given network namespace.
+    pub fn accepted(
+        &mut self,
+        net_namespace: u32,
+        address: IpAddr,
+        port: u16,
+        fd: u32,
+        owner: FlowOwner,
+        visible: bool,
+    ) {
+        let id = self.accepting.remove(&(net_namespace, address, port));
+        let Some(flow) = id.and_then(|id| self.flows.get_mut(&id)) else {
+            return;
+        };
+        flow.owner = owner;
+        flow.fd = Some(fd);
+        flow.visible |= visible;
+    }
+
+    /// Gives up on waiting for the connect(2) calls behind the connections closed before the given time, returning
+    /// them in the order they were closed. Connections that have been idle for too long are forgotten.
+    pub fn expire(&mut self, timestamp: u64) -> Vec<Flow> {
+        if timestamp >= self.next_idle_check {
+            self.forget_idle(timestamp);
+            self.next_idle_check = timestamp.saturating_add(IDLE_CHECK_INTERVAL);
+        }
+        let mut expired = Vec::new();
+        let flows = &mut self.flows;
+        self.connecting.retain(|_, id| {
+            let closed = flows.get(id).and_then(|flow| flow.closed);
+            match closed {
+                Some(closed) if closed.saturating_add(CONNECT_GRACE_PERIOD) <= timestamp => {
+                    expired.extend(flows.remove(id));
+                    false
+                }
+                _ => true,
+            }
+        });
+        expired.sort_by_key(|flow| flow.closed);
+        expired
+    }
+
+    /// Forgets the connections whose state hasn't changed for too long, without waiting for them to be closed.
+    fn forget_idle(&mut self, timestamp: u64) {
+        let idle: Vec<_> = self
+            .flows
+            .iter()
+            .filter(|(_, flow)| {
+                let timeout = match (flow.established, flow.close_reason) {
+                    (Some(_), CloseReason::Unknown) => ESTABLISHED_IDLE_TIMEOUT,
+                    _ => IDLE_TIMEOUT,
+                };
+                flow.closed.is_none() && flow.last_changed.saturating_add(timeout) <= timestamp
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in idle {
+            let Some(flow) = self.flows.remove(&id) else {
+                continue;
+            };
+            self.forget_tuple(&flow.tuple, id);
+            self.connecting.retain(|_, pending| *pending != id);
+            self.accepting.retain(|_, pending| *pending != id);
+        }
+    }
+
+    fn find(&mut self, tuple: &FlowTuple, net_namespace: u32) -> Option<u64> {
+        if let Some(id) = self.find_exact(tuple, net_namespace) {
+            return Some(id);
+        }
+        // An outbound connection that was just assigned a local port.
+        let unbound = FlowTuple { local_port: 0, ..*tuple };
+        let id = self.find_exact(&unbound, net_namespace)?;
+        self.forget_tuple(&unbound, id);
+        self.tuples.entry(*tuple).or_default().push(id);
+        if let Some(flow) = self.flows.get_mut(&id) {
+            flow.tuple = *tuple;
+        }
+        Some(id)
+    }
+
+    fn find_exact(&self, tuple: &FlowTuple, net_namespace: u32) -> Option<u64> {
+        // Connections in different network namespaces can share endpoints.
+        let ids = self.tuples.get(tuple)?;
+        ids.iter().find(|id| self.flows.get(id).is_some_and(|flow| flow.net_namespace == net_namespace)).copied()
+    }
+
+    fn open(
+        &mut self,
+        tuple: FlowTuple,
+        net_namespace: u32,
+        family: AddressFamily,
+        state: &TcpState,
+        timestamp: u64,
+        owner: FlowOwner,
+    ) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let direction = match state {
+            TcpState::SynSent => Direction::Outbound,
+            _ => Direction::Inbound,
+        };
+        match direction {
+            Direction::Outbound => {
+                self.connecting.insert((owner.pid, tuple.remote_address, tuple.remote_port), id);
+            }
+            Direction::Inbound => {
+                self.accepting.insert((net_namespace, tuple.remote_address, tuple.remote_port), id);
+            }
+        };
+        let handshake_started =
+            matches!(state, TcpState::SynSent | TcpState::SynReceived | TcpState::NewSynReceived).then_some(timestamp);
+        let flow = Flow {
+            tuple,
+            net_namespace,
+            family,
+            direction,
+            owner,
+            fd: None,
+            connect_result: None,
+            opened: timestamp,
+            established: None,
+            closed: None,
+            handshake_started,
+            last_changed: timestamp,
+            close_reason: CloseReason::Unknown,
+            visible: false,
+        };
+        self.flows.insert(id, flow);
+        self.tuples.entry(tuple).or_default().push(id);
+        id
+    }
+
+    fn forget_tuple(&mut self, tuple: &FlowTuple, id: u64) {
+        if let Some(ids) = self.tuples.get_mut(tuple) {
+            ids.retain(|existing| *existing != id);
+            if ids.is_empty() {
+                self.tuples.remove(tuple);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TcpState::*;
+
+    const CLIENT: &str = "10.0.0.1";
+    const SERVER: &str = "10.0.0.2";
+
+    fn owner(pid: u32) -> FlowOwner {
+        let identity = Identity { tid: pid, ppid: None, uid: 0, gid: 0, workload: None, net_namespace: None };
+        FlowOwner { pid, start_time: 0, command: "curl".into(), identity }
+    }
+
+    fn change(local_port: u16, old_state: TcpState, new_state: TcpState) -> StateChangeEvent {
+        StateChangeEvent {
+            family: AddressFamily::Ipv4,
+            source_address: CLIENT.parse().unwrap(),
+            source_port: local_port,
+            destination_address: SERVER.parse().unwrap(),
+            destination_port: 443,
+            old_state,
+            new_state,
+        }
+    }
+
+    // Feeds the state changes of an outbound connection to the tracker, which only assigns it a local port once its
+    // handshake started, and returns the connection once it's finished.
+    fn outbound(flows: &mut FlowTracker, states: &[(TcpState, TcpState)]) -> Option<Flow> {
+        let mut finished = None;
+        for (timestamp, (old_state, new_state)) in states.iter().enumerate() {
+            let local_port = if timestamp == 0 { 0 } else { 40000 };
+            let change = change(local_port, old_state.clone(), new_state.clone());
+            finished = flows.state_changed(&change, 1, timestamp as u64 * 1000, owner(7), true);
+            if timestamp == 0 {
+                assert!(flows.connected(7, SERVER.parse().unwrap(), 443, 3, -115, true).is_none());
+            }
+        }
+        finished
+    }
+
+    #[test]
+    fn close_reasons_follow_the_state_changes() {
+        let mut flows = FlowTracker::default();
+        let flow = outbound(
+            &mut flows,
+            &[
+                (Close, SynSent),
+                (SynSent, Established),
+                (Established, FinWait1),
+                (FinWait1, FinWait2),
+                (FinWait2, Close),
+            ],
+        )
+        .expect("connection should be finished");
+        assert_eq!(flow.close_reason, CloseReason::LocalClose);
+        assert_eq!(flow.direction, Direction::Outbound);
+        assert_eq!(flow.tuple.local_port, 40000);
+        assert_eq!((flow.connect_result, flow.fd), (Some(-115), Some(3)));
+        assert_eq!(flow.handshake(), Some(1000));
+        assert_eq!(flow.closed, Some(4000));
+
+        let flow = outbound(
+            &mut flows,
+            &[
+                (Close, SynSent),
+                (SynSent, Established),
+                (Established, CloseWait),
+                (CloseWait, LastAck),
+                (LastAck, Close),
+            ],
+        )
+        .unwrap();
+        assert_eq!(flow.close_reason, CloseReason::RemoteClose);
+
+        let flow = outbound(&mut flows, &[(Close, SynSent), (SynSent, Established), (Established, Close)]).unwrap();
+        assert_eq!(flow.close_reason, CloseReason::Reset);
+
+        let flow = outbound(&mut flows, &[(Close, SynSent), (SynSent, Close)]).unwrap();
+        assert_eq!(flow.close_reason, CloseReason::Failed);
+        assert!(flow.established.is_none());
+        assert!(flows.flows.is_empty());
+    }
+
+    #[test]
+    fn connections_refused_before_connect_returns_wait_for_it() {
+        let mut flows = FlowTracker::default();
+        let server = SERVER.parse().unwrap();
+        assert!(flows.state_changed(&change(0, Close, SynSent), 1, 0, owner(7), true).is_none());
+        assert!(flows.state_changed(&change(40000, SynSent, Close), 1, 1000, owner(0), false).is_none());
+        let flow = flows.connected(7, server, 443, 3, -111, true).expect("connection should be finished");
+        assert_eq!(flow.close_reason, CloseReason::Failed);
+        assert_eq!(flow.connect_result, Some(-111));
+        assert_eq!(flow.owner.pid, 7);
+
+        // Without connect(2) returning, the connection is given up on once the grace period is over.
+        flows.state_changed(&change(0, Close, SynSent), 1, 0, owner(7), true);
+        flows.state_changed(&change(40000, SynSent, Close), 1, 1000, owner(0), false);
+        assert!(flows.expire(1000 + CONNECT_GRACE_PERIOD - 1).is_empty());
+        let expired = flows.expire(1000 + CONNECT_GRACE_PERIOD);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].connect_result, None);
+        assert!(flows.connected(7, server, 443, 3, -111, true).is_none());
+    }
+
+    #[test]
+    fn connections_in_different_network_namespaces_are_told_apart() {
+        let mut flows = FlowTracker::default();
+        flows.state_changed(&change(0, Close, SynSent), 1, 0, owner(7), true);
+        flows.state_changed(&change(0, Close, SynSent), 2, 0, owner(8), true);
+        flows.connected(7, SERVER.parse().unwrap(), 443, 3, 0, true);
+        flows.connected(8, SERVER.parse().unwrap(), 443, 3, 0, true);
+        let flow = flows.state_changed(&change(40000, SynSent, Close), 2, 1000, owner(0), false).unwrap();
+        assert_eq!((flow.owner.pid, flow.net_namespace), (8, 2));
+        assert_eq!(flows.flows.len(), 1);
+    }
+}
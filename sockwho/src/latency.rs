@@ -0,0 +1,262 @@
+use crate::{event::StateChangeEvent, processor::TcpState};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+    net::IpAddr,
+    time::Duration,
+};
+
+// The width of the bar used for the largest bucket in a histogram.
+const BAR_WIDTH: u64 = 40;
+
+// How long a handshake is waited for before it's assumed the state change that finished it was missed, e.g. because
+// events were lost. Linux gives up on handshakes after a little over 2 minutes with the default number of SYN retries.
+const HANDSHAKE_TIMEOUT: u64 = 5 * 60 * 1_000_000_000;
+
+// How often handshakes are checked for having timed out, given that means going through all of them.
+const EXPIRY_INTERVAL: u64 = 10 * 1_000_000_000;
+
+/// A histogram of durations that uses power of two buckets, the same way bpftrace's `hist` does.
+#[derive(Clone, Debug, Default)]
+pub struct Histogram {
+    // The number of durations in every bucket. Bucket 0 holds durations shorter than 1us, and every bucket after it
+    // holds the ones in [2^(n-1), 2^n) microseconds.
+    buckets: Vec<u64>,
+    count: u64,
+    total: u64,
+}
+
+impl Histogram {
+    /// Records a duration, in nanoseconds.
+    pub fn record(&mut self, duration: u64) {
+        let micros = duration / 1000;
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.total += duration;
+    }
+
+    /// The number of durations recorded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The average duration, in nanoseconds.
+    pub fn average(&self) -> Option<u64> {
+        self.total.checked_div(self.count)
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let max = self.buckets.iter().copied().max().unwrap_or_default().max(1);
+        // Skip the empty buckets at the start, the ones at the end are never there.
+        let first = self.buckets.iter().position(|count| *count > 0).unwrap_or(self.buckets.len());
+        for (bucket, count) in self.buckets.iter().enumerate().skip(first) {
+            let (start, end) = match bucket {
+                0 => (0, 1),
+                _ => (1 << (bucket - 1), 1 << bucket),
+            };
+            let range = format!("[{:?}, {:?})", Duration::from_micros(start), Duration::from_micros(end));
+            let bar = "@".repeat((count * BAR_WIDTH / max) as usize);
+            writeln!(f, "{range:<24} {count:>8} |{bar:<width$}|", width = BAR_WIDTH as usize)?;
+        }
+        Ok(())
+    }
+}
+
+/// The latency of the TCP handshakes started by connect(2) calls, both for the ones that were established and the ones
+/// that failed.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectLatency {
+    pub established: Histogram,
+    pub failed: Histogram,
+}
+
+impl fmt::Display for ConnectLatency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} established", self.established.count())?;
+        if let Some(average) = self.established.average() {
+            write!(f, " (avg {:.3?})", Duration::from_nanos(average))?;
+        }
+        write!(f, ", {} failed", self.failed.count())?;
+        if let Some(average) = self.failed.average() {
+            write!(f, " (avg {:.3?})", Duration::from_nanos(average))?;
+        }
+        writeln!(f)?;
+        write!(f, "{}", self.established)?;
+        if self.failed.count() > 0 {
+            writeln!(f, "failed:")?;
+            write!(f, "{}", self.failed)?;
+        }
+        Ok(())
+    }
+}
+
+/// A handshake that's in progress.
+struct Handshake {
+    started: u64,
+    command: String,
+}
+
+/// Measures how long outbound TCP handshakes take, per destination and per process, using socket state changes.
+///
+/// A handshake starts when a socket moves to `SynSent` and ends when it moves to either `Established` or, if it
+/// failed, `Close`.
+#[derive(Default)]
+pub struct LatencyTracker {
+    // Sockets are moved to SynSent before they're assigned a local port, so handshakes in progress are found by the
+    // network namespace they're in and the rest of their endpoints. Concurrent handshakes between the same endpoints
+    // are assumed to finish in order.
+    handshakes: HashMap<(u32, IpAddr, IpAddr, u16), VecDeque<Handshake>>,
+    destinations: BTreeMap<(IpAddr, u16), ConnectLatency>,
+    processes: BTreeMap<String, ConnectLatency>,
+    // When handshakes are next checked for having timed out.
+    next_expiry: u64,
+}
+
+impl LatencyTracker {
+    /// Records a TCP socket state change in the given network namespace, made by the given process. Handshakes are
+    /// only measured if the state change that started them is visible, e.g. because it matched a filter.
+    pub fn state_changed(
+        &mut self,
+        change: &StateChangeEvent,
+        net_namespace: u32,
+        timestamp: u64,
+        command: &str,
+        visible: bool,
+    ) {
+        let StateChangeEvent { source_address, destination_address, destination_port, old_state, new_state, .. } =
+            change;
+        let key = (net_namespace, *source_address, *destination_address, *destination_port);
+        match (old_state, new_state) {
+            (_, TcpState::SynSent) if visible => {
+                let handshake = Handshake { started: timestamp, command: command.to_string() };
+                self.handshakes.entry(key).or_default().push_back(handshake);
+            }
+            (TcpState::SynSent, TcpState::Established | TcpState::Close) => {
+                let Some(handshakes) = self.handshakes.get_mut(&key) else {
+                    return;
+                };
+                let Some(Handshake { started, command }) = handshakes.pop_front() else {
+                    return;
+                };
+                if handshakes.is_empty() {
+                    self.handshakes.remove(&key);
+                }
+                let latency = timestamp.saturating_sub(started);
+                let established = *new_state == TcpState::Established;
+                let destination = self.destinations.entry((*destination_address, *destination_port)).or_default();
+                let process = self.processes.entry(command).or_default();
+                for latencies in [destination, process] {
+                    match established {
+                        true => latencies.established.record(latency),
+                        false => latencies.failed.record(latency),
+                    };
+                }
+            }
+            _ => (),
+        };
+    }
+
+    /// Forgets the handshakes that started too long before the given time to still be in progress.
+    pub fn expire(&mut self, timestamp: u64) {
+        if timestamp < self.next_expiry {
+            return;
+        }
+        self.next_expiry = timestamp.saturating_add(EXPIRY_INTERVAL);
+        self.handshakes.retain(|_, handshakes| {
+            // Handshakes between the same endpoints are in the order they started.
+            while handshakes.front().is_some_and(|h| h.started.saturating_add(HANDSHAKE_TIMEOUT) <= timestamp) {
+                handshakes.pop_front();
+            }
+            !handshakes.is_empty()
+        });
+    }
+
+    /// Whether no handshakes finished since the histograms were last cleared.
+    pub fn is_empty(&self) -> bool {
+        self.destinations.is_empty()
+    }
+
+    /// Clears the histograms, keeping track of the handshakes in progress.
+    pub fn clear(&mut self) {
+        self.destinations.clear();
+        self.processes.clear();
+    }
+}
+
+impl fmt::Display for LatencyTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Connect latency by destination")?;
+        for ((address, port), latency) in &self.destinations {
+            write!(f, "\n{address}:{port}: {latency}")?;
+        }
+        writeln!(f, "\nConnect latency by process")?;
+        for (command, latency) in &self.processes {
+            write!(f, "\n{command}: {latency}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sockwho_common::AddressFamily;
+
+    fn buckets(durations: &[u64]) -> Vec<u64> {
+        let mut histogram = Histogram::default();
+        for duration in durations {
+            histogram.record(*duration);
+        }
+        histogram.buckets
+    }
+
+    fn change(old_state: TcpState, new_state: TcpState) -> StateChangeEvent {
+        StateChangeEvent {
+            family: AddressFamily::Ipv4,
+            source_address: "10.0.0.1".parse().unwrap(),
+            source_port: 0,
+            destination_address: "10.0.0.2".parse().unwrap(),
+            destination_port: 443,
+            old_state,
+            new_state,
+        }
+    }
+
+    #[test]
+    fn histogram_buckets_start_at_powers_of_two() {
+        assert_eq!(buckets(&[0, 999]), [2]);
+        assert_eq!(buckets(&[1000, 1999]), [0, 2]);
+        assert_eq!(buckets(&[2000, 3999, 4000]), [0, 0, 2, 1]);
+        assert_eq!(buckets(&[1_048_576_000]), [vec![0; 21], vec![1]].concat());
+    }
+
+    #[test]
+    fn histogram_only_displays_buckets_from_the_first_used_one() {
+        let mut histogram = Histogram::default();
+        histogram.record(3000);
+        histogram.record(9000);
+        // The buckets for [2µs, 4µs), [4µs, 8µs) and [8µs, 16µs).
+        let display = histogram.to_string();
+        assert_eq!(display.lines().count(), 3);
+        assert!(display.starts_with("[2µs, 4µs)"));
+        assert_eq!(histogram.average(), Some(6000));
+    }
+
+    #[test]
+    fn handshakes_are_told_apart_by_network_namespace() {
+        let mut latency = LatencyTracker::default();
+        latency.state_changed(&change(TcpState::Close, TcpState::SynSent), 1, 1000, "curl", true);
+        latency.state_changed(&change(TcpState::Close, TcpState::SynSent), 2, 5000, "wget", true);
+        latency.state_changed(&change(TcpState::SynSent, TcpState::Established), 2, 8000, "swapper", true);
+        assert_eq!(latency.processes.keys().collect::<Vec<_>>(), ["wget"]);
+        assert_eq!(latency.processes["wget"].established.average(), Some(3000));
+        latency.state_changed(&change(TcpState::SynSent, TcpState::Close), 1, 9000, "swapper", true);
+        assert_eq!(latency.processes["curl"].failed.average(), Some(8000));
+    }
+}
@@ -1,5 +1,24 @@
 pub mod attach;
 pub mod bpf;
-pub(crate) mod errno;
+pub mod btf;
+pub mod capture;
+pub mod clock;
+pub mod container;
+pub mod errno;
+pub mod event;
+pub mod expression;
+pub mod filters;
+pub mod flows;
+pub mod latency;
+pub mod listeners;
 pub mod monitor;
+pub mod options;
+pub mod process;
 pub mod processor;
+pub mod ring_buffer;
+pub mod sink;
+pub mod socket;
+pub mod summary;
+pub mod top;
+pub mod tracefs;
+pub mod tui;
@@ -0,0 +1,163 @@
+use crate::processor::TcpState;
+use sockwho_common::AddressFamily;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+/// A socket that's listening for connections.
+#[derive(Clone, Debug)]
+pub struct Listener {
+    pub pid: u32,
+    pub command: String,
+    pub fd: Option<u32>,
+    pub backlog: Option<i64>,
+}
+
+/// A change in the set of listening sockets.
+#[derive(Clone, Debug)]
+pub enum ListenerChange {
+    Added(String, Listener),
+    Removed(String, Listener),
+}
+
+impl fmt::Display for ListenerChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (sign, address, listener) = match self {
+            Self::Added(address, listener) => ('+', address, listener),
+            Self::Removed(address, listener) => ('-', address, listener),
+        };
+        let Listener { pid, command, fd, backlog } = listener;
+        write!(f, "{sign} {address} {command}/{pid}")?;
+        if let Some(fd) = fd {
+            write!(f, "/{fd}")?;
+        }
+        if let Some(backlog) = backlog {
+            write!(f, " backlog={backlog}")?;
+        }
+        Ok(())
+    }
+}
+
+struct BoundAddress {
+    address: String,
+    family: AddressFamily,
+}
+
+/// Keeps track of which processes are listening on which addresses.
+///
+/// TCP listeners are tracked using the socket state changes, given those contain the actual address being listened on
+/// even when binding to port 0 or not binding at all. Unix domain sockets have no state changes so they're tracked
+/// using the address they were bound to.
+#[derive(Default)]
+pub struct ListenerTable {
+    bound: HashMap<(u32, u32), BoundAddress>,
+    // listen(2) calls on TCP sockets waiting for their state change, and the state changes waiting for their listen(2)
+    // call, by the thread that made the call given the state changes as part of it.
+    pending_calls: HashMap<u32, (u32, i64)>,
+    pending_changes: HashMap<u32, String>,
+    listeners: BTreeMap<String, Listener>,
+}
+
+impl ListenerTable {
+    /// Records that a socket was bound to an address.
+    pub fn bind(&mut self, pid: u32, fd: u32, address: String, family: AddressFamily) {
+        self.bound.insert((pid, fd), BoundAddress { address, family });
+    }
+
+    /// Records a successful call to listen(2) made by the given thread.
+    pub fn listen(&mut self, pid: u32, tid: u32, fd: u32, backlog: i64, command: &str) -> Option<ListenerChange> {
+        if let Some(BoundAddress { address, family: AddressFamily::Unix }) = self.bound.get(&(pid, fd)) {
+            if let Some(listener) = self.listeners.get_mut(address) {
+                // Calling listen(2) again only updates the backlog.
+                listener.backlog = Some(backlog);
+                return None;
+            }
+            let listener = Listener { pid, command: command.into(), fd: Some(fd), backlog: Some(backlog) };
+            self.listeners.insert(address.clone(), listener.clone());
+            return Some(ListenerChange::Added(address.clone(), listener));
+        }
+        // Calling listen(2) again on a TCP socket doesn't change its state either.
+        if let Some(listener) =
+            self.listeners.values_mut().find(|listener| listener.pid == pid && listener.fd == Some(fd))
+        {
+            listener.backlog = Some(backlog);
+            return None;
+        }
+        // The state change and the syscall are reported independently so either one can show up first.
+        let listener = self.pending_changes.remove(&tid).and_then(|address| self.listeners.get_mut(&address));
+        match listener {
+            Some(listener) if listener.pid == pid => {
+                listener.fd = Some(fd);
+                listener.backlog = Some(backlog);
+            }
+            _ => {
+                self.pending_calls.insert(tid, (fd, backlog));
+            }
+        };
+        None
+    }
+
+    /// Records a socket being closed.
+    pub fn closed(&mut self, pid: u32, fd: u32) -> Option<ListenerChange> {
+        let BoundAddress { address, family } = self.bound.remove(&(pid, fd))?;
+        // TCP listeners are removed when their state changes.
+        if !matches!(family, AddressFamily::Unix) {
+            return None;
+        }
+        let listener = self.listeners.get(&address)?;
+        if listener.pid != pid || listener.fd != Some(fd) {
+            return None;
+        }
+        let listener = self.listeners.remove(&address)?;
+        Some(ListenerChange::Removed(address, listener))
+    }
+
+    /// The sockets that are listening, by address.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Listener)> {
+        self.listeners.iter()
+    }
+
+    /// Records a TCP socket state change made by the given thread.
+    pub fn state_changed(
+        &mut self,
+        pid: u32,
+        tid: u32,
+        command: &str,
+        address: String,
+        old_state: &TcpState,
+        new_state: &TcpState,
+    ) -> Option<ListenerChange> {
+        if *new_state == TcpState::Listen {
+            let (fd, backlog) = match self.pending_calls.remove(&tid) {
+                Some((fd, backlog)) => (Some(fd), Some(backlog)),
+                None => {
+                    self.pending_changes.insert(tid, address.clone());
+                    (None, None)
+                }
+            };
+            let listener = Listener { pid, command: command.into(), fd, backlog };
+            self.listeners.insert(address.clone(), listener.clone());
+            Some(ListenerChange::Added(address, listener))
+        } else if *old_state == TcpState::Listen {
+            let listener = self.listeners.remove(&address)?;
+            Some(ListenerChange::Removed(address, listener))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for ListenerTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.listeners.keys().map(String::len).max().unwrap_or_default().max("ADDRESS".len());
+        writeln!(f, "{:<width$} {:<16} {:>8} {:>5} {:>8}", "ADDRESS", "PROCESS", "PID", "FD", "BACKLOG")?;
+        for (address, listener) in &self.listeners {
+            let Listener { pid, command, fd, backlog } = listener;
+            let fd = fd.map(|fd| fd.to_string()).unwrap_or_else(|| "-".into());
+            let backlog = backlog.map(|backlog| backlog.to_string()).unwrap_or_else(|| "-".into());
+            writeln!(f, "{address:<width$} {command:<16} {pid:>8} {fd:>5} {backlog:>8}")?;
+        }
+        Ok(())
+    }
+}
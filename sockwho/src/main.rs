@@ -1,18 +1,198 @@
-use anyhow::Error;
-use aya::{include_bytes_aligned, Bpf};
-use clap::{Parser, ValueEnum};
+use anyhow::{anyhow, bail, Context, Error};
+use aya::{include_bytes_aligned, Bpf, BpfLoader, Pod};
+use clap::{error::ErrorKind, CommandFactory, Parser, Subcommand, ValueEnum};
+use log::warn;
 use sockwho::{
     attach::{ProbeAttacherBuilder, Tracepoint},
-    monitor::{Monitor, MonitoredQueue},
-    processor::{EventProcessor, EventProcessorConfig},
+    bpf::BpfEvent,
+    btf,
+    capture::{CaptureHeader, CaptureReader, CaptureWriter},
+    clock::Clock,
+    container::CgroupResolver,
+    expression::FilterExpression,
+    filters::{KernelFilters, Network},
+    monitor::{Monitor, MonitoredQueue, Transport},
+    processor::{EventProcessor, EventProcessorConfig, OutputMode},
+    ring_buffer::{self, RingBuffer},
+    sink::{CsvSink, EventSink, JsonSink, TextSink},
+    summary::KernelSummary,
+    top::{TopSink, TopStats},
+    tracefs, tui,
+};
+use sockwho_common::{
+    KernelOffsets, NewTaskOffsets, ProcessExecEvent, SockaddrEvent, SocketCallEvent, SocketCreatedEvent,
+    SocketOptionEvent, SocketStateEvent, RING_BUFFER_SIZE,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    os::{
+        fd::AsRawFd,
+        unix::{net::UnixStream, process::ExitStatusExt},
+    },
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    pin,
+    process::Child,
+    select,
+    signal::{self, unix::SignalKind},
+    sync::mpsc::channel,
+    time::sleep,
 };
-use sockwho_common::{SockaddrEvent, SocketStateEvent};
 
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// The hooks to use.
     #[arg(value_enum, default_values_t = Hook::all())]
     hooks: Vec<Hook>,
+
+    /// How long to hold events for, in milliseconds, so the ones that are delivered out of order are displayed in the
+    /// order they happened.
+    #[arg(long, default_value_t = 50, global = true)]
+    reorder_window: u64,
+
+    /// Display the executable, command line and start time of the process behind every event, read from /proc.
+    #[arg(long, global = true)]
+    enrich: bool,
+
+    /// Only display the events generated by the container with this id, or id prefix, or the systemd unit with this
+    /// name.
+    #[arg(long, global = true)]
+    container: Option<String>,
+
+    /// Only trace the processes with these pids.
+    #[arg(long = "pid", value_name = "PID", value_delimiter = ',', global = true)]
+    pids: Vec<u32>,
+
+    /// Also trace the processes created by the ones given via --pid, and the ones created by those, and so on.
+    #[arg(long, requires = "pids", global = true)]
+    follow_children: bool,
+
+    /// Only trace the processes with these names.
+    #[arg(long = "comm", value_name = "NAME", value_delimiter = ',', global = true)]
+    commands: Vec<String>,
+
+    /// Only trace the sockets that use these ports, either locally or on the peer's side.
+    #[arg(long = "port", value_name = "PORT", value_delimiter = ',', global = true)]
+    ports: Vec<u16>,
+
+    /// Only trace the sockets that use addresses in these networks, e.g. 10.0.0.0/8, either locally or on the peer's
+    /// side.
+    #[arg(long = "net", value_name = "NETWORK", value_delimiter = ',', global = true)]
+    networks: Vec<Network>,
+
+    /// Only display the events that match this expression, e.g.
+    /// `syscall == connect and port == 443 and errno != EINPROGRESS and not comm ~ "^chrome"`.
+    #[arg(long, global = true)]
+    filter: Option<FilterExpression>,
+
+    /// Print a summary of every TCP connection once it's closed instead of every event.
+    #[arg(long, conflicts_with_all = ["latency", "summary"], global = true)]
+    flows: bool,
+
+    /// Print histograms of how long outbound TCP handshakes take, per destination and per process, instead of every
+    /// event. They're printed on exit, and every this many seconds if given.
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        num_args = 0..=1,
+        default_missing_value = "0",
+        conflicts_with = "summary",
+        global = true
+    )]
+    latency: Option<u64>,
+
+    /// Print how many times every process made every syscall, by address and result, every this many seconds instead
+    /// of every event.
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..), global = true)]
+    summary: Option<u64>,
+
+    /// Count the syscalls that use an IP address in the kernel rather than sending every one of them to userspace.
+    /// Their events can't be filtered by --filter or --container given they never reach userspace.
+    #[arg(long, requires = "summary", conflicts_with_all = ["filter", "container"], global = true)]
+    summary_in_kernel: bool,
+
+    /// How events are printed. The reports of --latency and --summary, and the listeners table, are always text.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, global = true)]
+    output: OutputFormat,
+
+    /// How events are sent from the kernel.
+    #[arg(long, value_enum, default_value_t = TransportType::Auto, global = true)]
+    transport: TransportType,
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+enum TransportType {
+    /// Use a ring buffer if the kernel supports it, otherwise use perf buffers.
+    Auto,
+
+    /// Use a single ring buffer shared by all CPUs, which requires Linux 5.8 or newer.
+    RingBuffer,
+
+    /// Use a perf buffer per CPU and event type.
+    PerfBuffer,
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+enum OutputFormat {
+    /// A line of text per event.
+    Text,
+
+    /// A JSON object per event, one per line.
+    Json,
+
+    /// A CSV record per event, preceded by a header.
+    Csv,
+}
+
+impl OutputFormat {
+    fn sink(self) -> Box<dyn EventSink> {
+        match self {
+            OutputFormat::Text => Box::new(TextSink::stdout()),
+            OutputFormat::Json => Box::new(JsonSink::stdout()),
+            OutputFormat::Csv => Box::new(CsvSink::stdout()),
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Display a live table of the sockets listening for connections.
+    Listeners,
+
+    /// Display live tables of the busiest processes and destinations, the listening sockets and the failed connects.
+    Top,
+
+    /// Write the events to a capture file until interrupted, so they can be replayed later, e.g. on another machine.
+    Record {
+        /// The file to write the events to.
+        #[arg(short = 'w', long = "write", value_name = "FILE")]
+        path: PathBuf,
+    },
+
+    /// Process the events in a capture file written by `record` the same way as if they were being traced, which
+    /// doesn't require root or BPF support.
+    Replay {
+        /// The file to read the events from.
+        #[arg(short = 'r', long = "read", value_name = "FILE")]
+        path: PathBuf,
+    },
+
+    /// Run a command and only trace it and its descendants, exiting with its exit code once it exits.
+    Run {
+        /// The command to run, followed by its arguments.
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
@@ -21,36 +201,264 @@ enum Hook {
     Connect,
     RecvFrom,
     SendTo,
+    Accept,
+    SendMsg,
+    RecvMsg,
+    SendMmsg,
+    RecvMmsg,
+    Socket,
+    Listen,
+    Shutdown,
+    Close,
+    GetSockName,
+    GetPeerName,
+    SetSockOpt,
     SocketState,
 }
 
 impl Hook {
     fn all() -> Vec<Hook> {
-        vec![Hook::Bind, Hook::Connect, Hook::RecvFrom, Hook::SendTo, Hook::SocketState]
+        vec![
+            Hook::Bind,
+            Hook::Connect,
+            Hook::RecvFrom,
+            Hook::SendTo,
+            Hook::Accept,
+            Hook::SendMsg,
+            Hook::RecvMsg,
+            Hook::SendMmsg,
+            Hook::RecvMmsg,
+            Hook::Socket,
+            Hook::Listen,
+            Hook::Shutdown,
+            Hook::Close,
+            Hook::GetSockName,
+            Hook::GetPeerName,
+            Hook::SetSockOpt,
+            Hook::SocketState,
+        ]
     }
-}
 
-impl From<Hook> for Tracepoint {
-    fn from(hook: Hook) -> Self {
+    fn tracepoints(&self) -> Vec<Tracepoint> {
         use Hook::*;
-        match hook {
-            Bind => Tracepoint::syscall("bind"),
-            Connect => Tracepoint::syscall("connect"),
-            RecvFrom => Tracepoint::syscall("recvfrom"),
-            SendTo => Tracepoint::syscall("sendto"),
-            SocketState => Tracepoint::socket("inet_sock_set_state"),
+        match self {
+            Bind => vec![Tracepoint::syscall("bind")],
+            Connect => vec![Tracepoint::syscall("connect")],
+            RecvFrom => vec![Tracepoint::syscall("recvfrom")],
+            SendTo => vec![Tracepoint::syscall("sendto")],
+            Accept => vec![Tracepoint::syscall("accept"), Tracepoint::syscall("accept4")],
+            SendMsg => vec![Tracepoint::syscall("sendmsg")],
+            RecvMsg => vec![Tracepoint::syscall("recvmsg")],
+            SendMmsg => vec![Tracepoint::syscall("sendmmsg")],
+            RecvMmsg => vec![Tracepoint::syscall("recvmmsg")],
+            Socket => vec![Tracepoint::syscall("socket")],
+            Listen => vec![Tracepoint::syscall("listen")],
+            Shutdown => vec![Tracepoint::syscall("shutdown")],
+            // Only close(2) calls on file descriptors that other hooks found to be sockets are traced.
+            Close => vec![Tracepoint::syscall("close")],
+            GetSockName => vec![Tracepoint::syscall("getsockname")],
+            GetPeerName => vec![Tracepoint::syscall("getpeername")],
+            SetSockOpt => vec![Tracepoint::syscall("setsockopt")],
+            SocketState => vec![Tracepoint::socket("inet_sock_set_state")],
+        }
+    }
+
+    /// The kprobes used to resolve the peer of connected sockets when no address is given.
+    fn kprobes(&self) -> Vec<&'static str> {
+        use Hook::*;
+        match self {
+            RecvFrom | RecvMsg => vec!["inet_recvmsg", "inet6_recvmsg"],
+            SendTo | SendMsg => vec!["inet_sendmsg", "inet6_sendmsg"],
+            _ => vec![],
         }
     }
 }
 
-fn load_bpf() -> Result<Bpf, Error> {
+fn load_bpf(
+    transport: TransportType,
+    filters: &KernelFilters,
+    summarize: bool,
+    offsets: &KernelOffsets,
+) -> Result<(Bpf, Transport), Error> {
     #[cfg(debug_assertions)]
     let bytes = include_bytes_aligned!("../../target/bpfel-unknown-none/debug/sockwho");
 
     #[cfg(not(debug_assertions))]
     let bytes = include_bytes_aligned!("../../target/bpfel-unknown-none/release/sockwho");
 
-    Ok(Bpf::load(bytes)?)
+    let use_ring_buffer = match transport {
+        TransportType::Auto => ring_buffer::is_supported(),
+        TransportType::RingBuffer => true,
+        TransportType::PerfBuffer => false,
+    };
+    // The tracepoint these are for is only attached when following children.
+    let new_task = if filters.follow_children {
+        tracefs::new_task_offsets().context("reading the format of the task_newtask tracepoint")?
+    } else {
+        NewTaskOffsets::default()
+    };
+    let globals = (
+        use_ring_buffer as u8,
+        filters.flags(),
+        summarize as u8,
+        GlobalOffsets(*offsets),
+        GlobalNewTaskOffsets(new_task),
+    );
+    let mut loader = BpfLoader::new();
+    loader
+        .set_global("USE_RING_BUFFER", &globals.0)
+        .set_global("FILTERS", &globals.1)
+        .set_global("SUMMARIZE", &globals.2)
+        .set_global("KERNEL_OFFSETS", &globals.3)
+        .set_global("NEW_TASK_OFFSETS", &globals.4);
+    if !use_ring_buffer {
+        // Nothing is pinned without the ring buffer, so the BPF filesystem isn't needed.
+        let bpf = loader.load(&ring_buffer::replace_with_placeholder(bytes, "EVENTS")?)?;
+        filters.populate(&bpf)?;
+        return Ok((bpf, Transport::PerfBuffers));
+    }
+    // The ring buffer is pinned so we can get a handle to it. The pins are only needed while loading the programs.
+    let pin_path = PathBuf::from(format!("/sys/fs/bpf/sockwho-{}", process::id()));
+    fs::create_dir_all(&pin_path).context("creating directory in bpffs, is it mounted?")?;
+    let ring_buffer_path = pin_path.join("EVENTS");
+    let result = (|| {
+        let bpf = loader.map_pin_path(&pin_path).load(bytes)?;
+        filters.populate(&bpf)?;
+        let ring_buffer = RingBuffer::open_pinned(&ring_buffer_path, RING_BUFFER_SIZE as usize)?;
+        Ok((bpf, Transport::RingBuffer(ring_buffer)))
+    })();
+    let _ = fs::remove_file(&ring_buffer_path);
+    let _ = fs::remove_dir(&pin_path);
+    result
+}
+
+// The offsets are shared with the eBPF programs, so they're wrapped to be usable as a global.
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+struct GlobalOffsets(KernelOffsets);
+
+unsafe impl Pod for GlobalOffsets {}
+
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+struct GlobalNewTaskOffsets(NewTaskOffsets);
+
+unsafe impl Pod for GlobalNewTaskOffsets {}
+
+/// Spawns a command and adds it to the pid filter before it runs, so none of its events are missed.
+fn spawn_traced(bpf: &Bpf, command: &[String]) -> Result<Child, Error> {
+    // The child blocks until it reads from this socket, which happens once it's been added to the filter. Both ends are
+    // closed on exec.
+    let (mut writer, reader) = UnixStream::pair()?;
+    let (writer_fd, reader_fd) = (writer.as_raw_fd(), reader.as_raw_fd());
+    let mut builder = tokio::process::Command::new(&command[0]);
+    builder.args(&command[1..]);
+    unsafe {
+        builder.pre_exec(move || {
+            // If we go away before unblocking the child, closing our copy of the writer makes the read fail.
+            libc::close(writer_fd);
+            let mut byte = 0_u8;
+            if libc::read(reader_fd, &mut byte as *mut u8 as *mut libc::c_void, 1) != 1 {
+                return Err(io::Error::other("sockwho exited before starting the command"));
+            }
+            Ok(())
+        })
+    };
+    let mut child = builder.spawn().with_context(|| format!("running '{}'", command[0]))?;
+    drop(reader);
+    let pid = child.id().ok_or_else(|| anyhow!("command exited before starting"))?;
+    let result = KernelFilters::add_pid(bpf, pid).and_then(|_| Ok(writer.write_all(&[0])?));
+    if let Err(e) = result {
+        let _ = child.start_kill();
+        return Err(e);
+    }
+    Ok(child)
+}
+
+fn queues() -> Vec<MonitoredQueue> {
+    vec![
+        MonitoredQueue::new::<SockaddrEvent>("SOCKADDR_EVENTS"),
+        MonitoredQueue::new::<SocketStateEvent>("SOCKET_STATE_EVENTS"),
+        MonitoredQueue::new::<SocketCreatedEvent>("SOCKET_CREATED_EVENTS"),
+        MonitoredQueue::new::<SocketCallEvent>("SOCKET_CALL_EVENTS"),
+        MonitoredQueue::new::<SocketOptionEvent>("SOCKET_OPTION_EVENTS"),
+        MonitoredQueue::new::<ProcessExecEvent>("PROCESS_EXEC_EVENTS"),
+    ]
+}
+
+/// Writes the events generated by the eBPF programs to a capture until interrupted.
+async fn record(
+    bpf: &Bpf,
+    transport: Transport,
+    path: &Path,
+    filter: Option<FilterExpression>,
+    container: Option<String>,
+) -> Result<(), Error> {
+    let mut writer = CaptureWriter::create(path, &CaptureHeader::current()?)?;
+    let (sender, mut receiver) = channel(1024);
+    Monitor::new(sender, queues()).launch(bpf, transport)?;
+    let mut cgroups = CgroupResolver::new();
+    // The workloads written so far, which are written again if they're resolved differently later on, e.g. because
+    // their cgroup wasn't found in cgroupfs yet.
+    let mut workloads = HashMap::new();
+    let mut count = 0;
+    let mut write = |event: BpfEvent| -> Result<(), Error> {
+        let workload = cgroups.workload(event.cgroup_id(), event.pid());
+        if let Some(container) = &container {
+            if !workload.as_ref().is_some_and(|workload| workload.matches(container)) {
+                return Ok(());
+            }
+        }
+        if filter.as_ref().is_none_or(|filter| filter.matches(&event)) {
+            // Recorded so the events can be filtered by and displayed with their workload when replaying.
+            if workloads.get(&event.cgroup_id()) != Some(&workload) {
+                writer.write_workload(event.cgroup_id(), &workload)?;
+                workloads.insert(event.cgroup_id(), workload);
+            }
+            writer.write(&event)?;
+            count += 1;
+        }
+        Ok(())
+    };
+    let interrupted = signal::ctrl_c();
+    pin!(interrupted);
+    loop {
+        select! {
+            event = receiver.recv() => match event {
+                Some(event) => write(event)?,
+                None => break,
+            },
+            _ = &mut interrupted => break,
+        };
+    }
+    while let Ok(event) = receiver.try_recv() {
+        write(event)?;
+    }
+    writer.flush()?;
+    eprintln!("{count} events written to {}", path.display());
+    Ok(())
+}
+
+/// Processes the events in a capture the same way as if they were being traced.
+async fn replay(path: &Path, mut config: EventProcessorConfig) -> Result<(), Error> {
+    let mut reader = CaptureReader::open(path)?;
+    let header = reader.header();
+    config.clock = Clock::from_boot_time(header.boot_time);
+    config.replay = Some(reader.machine());
+    // Events are replayed as fast as they can be read, so reports are only printed once they've all been processed.
+    config.report_interval = None;
+    let processor = EventProcessor::new(config);
+    let sender = processor.sender();
+    let stop = processor.stop_handle();
+    let processor = tokio::spawn(processor.run());
+    while let Some(event) = reader.read()? {
+        if sender.send(event).await.is_err() {
+            // The processor only stops on its own if it failed, which it returns below.
+            break;
+        }
+    }
+    stop.notify_one();
+    processor.await?
 }
 
 #[tokio::main]
@@ -59,27 +467,165 @@ async fn main() -> Result<(), Error> {
 
     let cli = Cli::parse();
 
-    let mut hooks = cli.hooks;
+    let (mut hooks, mode) = match cli.command {
+        Some(Command::Listeners) => {
+            (vec![Hook::Bind, Hook::Listen, Hook::Close, Hook::SocketState], OutputMode::Listeners)
+        }
+        Some(Command::Top | Command::Record { .. }) => (cli.hooks, OutputMode::Events),
+        Some(Command::Run { .. } | Command::Replay { .. }) | None if cli.latency.is_some() => {
+            (vec![Hook::SocketState], OutputMode::Latency)
+        }
+        Some(Command::Run { .. } | Command::Replay { .. }) | None if cli.flows => {
+            (vec![Hook::Connect, Hook::Accept, Hook::SocketState], OutputMode::Flows)
+        }
+        Some(Command::Run { .. } | Command::Replay { .. }) | None if cli.summary.is_some() => {
+            (cli.hooks, OutputMode::Summary)
+        }
+        Some(Command::Run { .. } | Command::Replay { .. }) | None => (cli.hooks, OutputMode::Events),
+    };
     hooks.sort();
     hooks.dedup();
+    // Reports and the listeners table aren't events, so they're only ever printed as text.
+    let reports = matches!(mode, OutputMode::Latency | OutputMode::Summary | OutputMode::Listeners);
+    if reports && !matches!(cli.output, OutputFormat::Text) {
+        Cli::command()
+            .error(ErrorKind::ArgumentConflict, "--output can only be text with --latency, --summary and listeners")
+            .exit();
+    }
 
-    let mut bpf = load_bpf()?;
+    let top = matches!(cli.command, Some(Command::Top));
+    let (run_command, record_path, replay_path) = match cli.command {
+        Some(Command::Run { command }) => (Some(command), None, None),
+        Some(Command::Record { path }) => (None, Some(path), None),
+        Some(Command::Replay { path }) => (None, None, Some(path)),
+        _ => (None, None, None),
+    };
+    let mut filters = KernelFilters {
+        pids: cli.pids,
+        commands: cli.commands,
+        ports: cli.ports,
+        networks: cli.networks,
+        // The command is added to the pid filter once it's spawned.
+        follow_children: cli.follow_children || run_command.is_some(),
+    };
+    if let Some(filter) = &cli.filter {
+        filter.push_down(&mut filters);
+    }
+    let reorder_window = Duration::from_millis(cli.reorder_window);
+    let top_stats = Arc::new(Mutex::new(TopStats::default()));
+    let sink: Box<dyn EventSink> = match top {
+        true => Box::new(TopSink::new(top_stats.clone())),
+        false => cli.output.sink(),
+    };
+    let mut config = EventProcessorConfig {
+        channel_size: 1024,
+        mode,
+        sinks: vec![sink],
+        reorder_window,
+        enrich: cli.enrich,
+        container: cli.container,
+        filter: cli.filter,
+        report_interval: match mode {
+            OutputMode::Latency => cli.latency.filter(|seconds| *seconds > 0),
+            OutputMode::Summary => cli.summary,
+            _ => None,
+        }
+        .map(Duration::from_secs),
+        kernel_summary: None,
+        clock: Clock::new(),
+        replay: None,
+    };
+    if let Some(path) = replay_path {
+        if config.enrich || filters.follow_children || cli.summary_in_kernel {
+            bail!("--enrich, --follow-children and --summary-in-kernel can't be used when replaying");
+        }
+        config.filter = match (FilterExpression::from_kernel_filters(&filters), config.filter) {
+            (Some(kernel), Some(filter)) => Some(kernel.and(filter)),
+            (kernel, filter) => kernel.or(filter),
+        };
+        return replay(&path, config).await;
+    }
+    let summarize = mode == OutputMode::Summary && cli.summary_in_kernel;
+    // Without them, the peer of connected sockets can't be resolved so the kprobes that do it aren't attached.
+    let offsets = btf::kernel_offsets();
+    if let Err(e) = &offsets {
+        warn!("Not resolving the peer of connected sockets: {e:#}");
+    }
+    let (mut bpf, transport) =
+        load_bpf(cli.transport, &filters, summarize, offsets.as_ref().unwrap_or(&KernelOffsets::default()))?;
+    if summarize {
+        config.kernel_summary = Some(KernelSummary::new(&bpf)?);
+    }
     let mut builder = ProbeAttacherBuilder::new(&mut bpf);
-    for hook in hooks {
-        builder = builder.with_tracepoint(hook.into());
+    for tracepoint in hooks.iter().flat_map(Hook::tracepoints) {
+        builder = builder.with_tracepoint(tracepoint);
+    }
+    // Some kprobes are shared by more than one hook.
+    let mut kprobes: Vec<_> = hooks.iter().flat_map(Hook::kprobes).collect();
+    kprobes.sort();
+    kprobes.dedup();
+    for kprobe in kprobes.into_iter().filter(|_| offsets.is_ok()) {
+        builder = builder.with_kprobe(kprobe);
+    }
+    if cli.enrich {
+        // Used to read the information about processes that exit before their events are processed.
+        builder = builder.with_tracepoint(Tracepoint::sched("sched_process_exec"));
+    }
+    if filters.follow_children {
+        builder = builder
+            .with_tracepoint(Tracepoint::task("task_newtask"))
+            .with_tracepoint(Tracepoint::sched("sched_process_exit"));
     }
     let mut attacher = builder.build();
     attacher.attach_tracepoints()?;
+    attacher.attach_kprobes()?;
+
+    if let Some(path) = record_path {
+        return record(&bpf, transport, &path, config.filter, config.container).await;
+    }
 
-    let config = EventProcessorConfig { channel_size: 1024 };
     let processor = EventProcessor::new(config);
-    let queues = vec![
-        MonitoredQueue::new::<SockaddrEvent>("SOCKADDR_EVENTS"),
-        MonitoredQueue::new::<SocketStateEvent>("SOCKET_STATE_EVENTS"),
-    ];
-    let monitor = Monitor::new(processor.sender(), queues);
-    monitor.launch(&bpf)?;
-    processor.run().await;
+    let monitor = Monitor::new(processor.sender(), queues());
+    monitor.launch(&bpf, transport)?;
 
-    Ok(())
+    let stop = processor.stop_handle();
+    let mut processor = tokio::spawn(processor.run());
+    if top {
+        tokio::task::spawn_blocking(move || tui::run(top_stats, Duration::from_secs(1))).await??;
+        stop.notify_one();
+        return processor.await?;
+    }
+    let Some(command) = run_command else {
+        // Stop on ctrl-c or SIGTERM so the pending events are processed and the reports printed, unless the events
+        // can't be written anymore, e.g. because we're piped into head(1).
+        let mut terminate = signal::unix::signal(SignalKind::terminate())?;
+        select! {
+            result = signal::ctrl_c() => result?,
+            _ = terminate.recv() => (),
+            result = &mut processor => return result?,
+        }
+        stop.notify_one();
+        return processor.await?;
+    };
+    // Interrupting us also interrupts the command, so keep running until it exits either way.
+    let _interrupts = signal::unix::signal(SignalKind::interrupt())?;
+    let mut child = spawn_traced(&bpf, &command)?;
+    let mut terminate = signal::unix::signal(SignalKind::terminate())?;
+    let status = loop {
+        select! {
+            status = child.wait() => break status?,
+            _ = terminate.recv() => {
+                // Pass it on to the command, which then exits like it would have if it had gotten it itself.
+                if let Some(pid) = child.id() {
+                    unsafe { libc::kill(pid as i32, libc::SIGTERM) };
+                }
+            }
+            result = &mut processor => return result?,
+        }
+    };
+    // Give the events generated right before the command exited time to make it through the reorder window.
+    sleep(reorder_window + Duration::from_millis(100)).await;
+    stop.notify_one();
+    processor.await??;
+    process::exit(status.code().unwrap_or_else(|| 128 + status.signal().unwrap_or_default()));
 }
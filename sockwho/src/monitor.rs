@@ -1,18 +1,28 @@
-use crate::bpf::BpfEvent;
+use crate::{bpf::BpfEvent, ring_buffer::RingBuffer};
 use anyhow::Error;
 use aya::{
     maps::{
         perf::{AsyncPerfEventArray, AsyncPerfEventArrayBuffer},
-        MapRefMut,
+        MapRefMut, PerCpuArray,
     },
     util::online_cpus,
     Bpf,
 };
 use bytes::BytesMut;
 use log::warn;
-use std::{mem::size_of, sync::Arc};
+use sockwho_common::{Event, RING_BUFFER_HEADER_SIZE};
+use std::{collections::HashMap, mem::size_of, sync::Arc};
 use tokio::sync::mpsc::Sender;
 
+/// How events are read from the eBPF programs.
+pub enum Transport {
+    /// One perf event array per event type, each of which has a buffer per CPU.
+    PerfBuffers,
+
+    /// A single ring buffer shared by all CPUs and event types, which keeps events in order.
+    RingBuffer(RingBuffer),
+}
+
 pub struct Monitor {
     sender: Sender<BpfEvent>,
     queues: Vec<MonitoredQueue>,
@@ -23,7 +33,19 @@ impl Monitor {
         Self { sender, queues }
     }
 
-    pub fn launch(self, bpf: &Bpf) -> Result<(), Error> {
+    pub fn launch(self, bpf: &Bpf, transport: Transport) -> Result<(), Error> {
+        match transport {
+            Transport::PerfBuffers => self.launch_perf_buffers(bpf),
+            Transport::RingBuffer(ring_buffer) => {
+                let builders = self.queues.into_iter().map(|queue| (queue.kind, queue.event_builder)).collect();
+                let dropped = PerCpuArray::try_from(bpf.map_mut("DROPPED_EVENTS")?)?;
+                tokio::task::spawn(Self::process_ring_buffer(ring_buffer, builders, dropped, self.sender));
+                Ok(())
+            }
+        }
+    }
+
+    fn launch_perf_buffers(self, bpf: &Bpf) -> Result<(), Error> {
         let cpus = online_cpus()?;
         let mut event_queues = Vec::new();
         for queue in self.queues {
@@ -68,37 +90,85 @@ impl Monitor {
             }
         }
     }
+
+    async fn process_ring_buffer(
+        mut ring_buffer: RingBuffer,
+        builders: HashMap<u32, EventBuilder>,
+        dropped: PerCpuArray<MapRefMut, u64>,
+        sender: Sender<BpfEvent>,
+    ) {
+        let mut events = Vec::new();
+        // The number of events the eBPF programs couldn't send that were already reported.
+        let mut reported = 0;
+        loop {
+            let result = ring_buffer
+                .read_records(|record| {
+                    let Some(kind) =
+                        record.get(..4).map(|kind| u32::from_ne_bytes([kind[0], kind[1], kind[2], kind[3]]))
+                    else {
+                        return;
+                    };
+                    match builders.get(&kind) {
+                        Some(builder) if record.len() >= RING_BUFFER_HEADER_SIZE + builder.event_size => {
+                            events.push(builder.build(&record[RING_BUFFER_HEADER_SIZE..]));
+                        }
+                        Some(_) => warn!("Ignoring truncated event of kind {kind}"),
+                        None => warn!("Ignoring event of unknown kind {kind}"),
+                    };
+                })
+                .await;
+            if let Err(e) = result {
+                warn!("Failed to read from ring buffer: {e}");
+                return;
+            }
+            if let Ok(counts) = dropped.get(&0, 0) {
+                let lost = counts.iter().sum::<u64>();
+                if lost > reported {
+                    warn!("Lost {} events", lost - reported);
+                    reported = lost;
+                }
+            }
+            for event in events.drain(..) {
+                if sender.send(event).await.is_err() {
+                    warn!("Failed to send event to consumer");
+                }
+            }
+        }
+    }
 }
 
+type BuildEvent = dyn Fn(&[u8]) -> BpfEvent + Send + Sync;
+
 #[derive(Clone)]
 pub struct EventBuilder {
     event_size: usize,
-    builder: Arc<dyn Fn(&BytesMut) -> BpfEvent + Send + Sync>,
+    builder: Arc<BuildEvent>,
 }
 
 impl EventBuilder {
-    fn build(&self, bytes: &BytesMut) -> BpfEvent {
+    fn build(&self, bytes: &[u8]) -> BpfEvent {
         (self.builder)(bytes)
     }
 }
 
 pub struct MonitoredQueue {
     name: String,
+    kind: u32,
     event_builder: EventBuilder,
 }
 
 impl MonitoredQueue {
-    pub fn new<T>(name: &str) -> Self
+    pub fn new<T: Event>(name: &str) -> Self
     where
         BpfEvent: From<T>,
     {
         let name = name.into();
-        let builder = Arc::new(|buffer: &BytesMut| {
+        let builder = Arc::new(|buffer: &[u8]| {
             let ptr = buffer.as_ptr() as *const T;
             let event = unsafe { ptr.read_unaligned() };
             BpfEvent::from(event)
         });
         let event_builder = EventBuilder { event_size: size_of::<T>(), builder };
-        Self { name, event_builder }
+        Self { name, kind: T::KIND as u32, event_builder }
     }
 }
@@ -0,0 +1,156 @@
+use enum_primitive_derive::Primitive;
+use num_traits::FromPrimitive;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use std::fmt;
+
+/// A socket option set via setsockopt(2).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SocketOption {
+    pub level: i32,
+    pub name: i32,
+    pub value: OptionValue,
+}
+
+/// The value of a socket option.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum OptionValue {
+    /// An integer or boolean option, e.g. SO_REUSEADDR.
+    Integer(i32),
+
+    /// A string option, e.g. the interface name used with SO_BINDTODEVICE.
+    String(String),
+
+    /// Any other value, truncated to the bytes that were captured.
+    Bytes { data: Vec<u8>, length: u32 },
+}
+
+impl SocketOption {
+    /// Decodes an option from the first bytes of its value and its full length.
+    pub fn new(level: i32, name: i32, value: &[u8], length: u32) -> Self {
+        let value = match Self::known_name(level, name) {
+            Some(KnownOption::BindToDevice) => {
+                let end = value.iter().position(|c| *c == 0).unwrap_or(value.len());
+                OptionValue::String(String::from_utf8_lossy(&value[..end]).into_owned())
+            }
+            _ if length == 4 && value.len() >= 4 => {
+                OptionValue::Integer(i32::from_ne_bytes([value[0], value[1], value[2], value[3]]))
+            }
+            _ => OptionValue::Bytes { data: value.to_vec(), length },
+        };
+        Self { level, name, value }
+    }
+
+    /// Whether this option changes which addresses a socket can bind or connect to.
+    pub fn affects_addressing(&self) -> bool {
+        Self::known_name(self.level, self.name).is_some()
+    }
+
+    fn known_name(level: i32, name: i32) -> Option<KnownOption> {
+        use KnownOption::*;
+        match (OptionLevel::from_i32(level)?, name) {
+            (OptionLevel::SOL_SOCKET, 2) => Some(ReuseAddr),
+            (OptionLevel::SOL_SOCKET, 15) => Some(ReusePort),
+            (OptionLevel::SOL_SOCKET, 25) => Some(BindToDevice),
+            (OptionLevel::SOL_SOCKET, 6) => Some(Broadcast),
+            (OptionLevel::SOL_IP, 15) => Some(IpFreebind),
+            (OptionLevel::SOL_IP, 19) => Some(IpTransparent),
+            (OptionLevel::SOL_IPV6, 26) => Some(Ipv6V6Only),
+            (OptionLevel::SOL_IPV6, 75) => Some(Ipv6Transparent),
+            (OptionLevel::SOL_IPV6, 78) => Some(Ipv6Freebind),
+            _ => None,
+        }
+    }
+
+    /// The name of this option, e.g. "SO_REUSEADDR".
+    pub fn name(&self) -> String {
+        match Self::known_name(self.level, self.name) {
+            Some(name) => format!("{name}"),
+            None => self.name.to_string(),
+        }
+    }
+}
+
+/// Displays the option the same way strace does, e.g. `SOL_SOCKET, SO_REUSEADDR, 1`.
+impl fmt::Display for SocketOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match OptionLevel::from_i32(self.level) {
+            Some(level) => write!(f, "{level:?}, ")?,
+            None => write!(f, "{}, ", self.level)?,
+        };
+        write!(f, "{}, {}", self.name(), self.value)
+    }
+}
+
+/// Serializes the option's name the same way it's displayed, e.g. `{"level": 1, "name": "SO_REUSEADDR", "value": 1}`.
+impl Serialize for SocketOption {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut option = serializer.serialize_struct("SocketOption", 3)?;
+        option.serialize_field("level", &self.level)?;
+        option.serialize_field("name", &self.name())?;
+        option.serialize_field("value", &self.value)?;
+        option.end()
+    }
+}
+
+impl fmt::Display for OptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Integer(value) => write!(f, "{value}"),
+            Self::String(value) => write!(f, "{value:?}"),
+            Self::Bytes { data, length } => {
+                write!(f, "\"")?;
+                for byte in data {
+                    write!(f, "\\x{byte:02x}")?;
+                }
+                if data.len() < *length as usize {
+                    write!(f, "\"...")
+                } else {
+                    write!(f, "\"")
+                }
+            }
+        }
+    }
+}
+
+/// The options that affect addressing, which are decoded by name.
+#[derive(Debug)]
+enum KnownOption {
+    ReuseAddr,
+    ReusePort,
+    BindToDevice,
+    Broadcast,
+    IpFreebind,
+    IpTransparent,
+    Ipv6V6Only,
+    Ipv6Transparent,
+    Ipv6Freebind,
+}
+
+impl fmt::Display for KnownOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use KnownOption::*;
+        let name = match self {
+            ReuseAddr => "SO_REUSEADDR",
+            ReusePort => "SO_REUSEPORT",
+            BindToDevice => "SO_BINDTODEVICE",
+            Broadcast => "SO_BROADCAST",
+            IpFreebind => "IP_FREEBIND",
+            IpTransparent => "IP_TRANSPARENT",
+            Ipv6V6Only => "IPV6_V6ONLY",
+            Ipv6Transparent => "IPV6_TRANSPARENT",
+            Ipv6Freebind => "IPV6_FREEBIND",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Primitive)]
+enum OptionLevel {
+    SOL_IP = 0,
+    SOL_SOCKET = 1,
+    SOL_TCP = 6,
+    SOL_UDP = 17,
+    SOL_IPV6 = 41,
+}
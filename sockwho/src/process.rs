@@ -0,0 +1,73 @@
+use lru::LruCache;
+use std::{fs, num::NonZeroUsize};
+
+/// Information about a process that isn't available to the eBPF programs.
+#[derive(Clone, Debug, Default)]
+pub struct ProcessInfo {
+    /// The name of the process, as opposed to the name of the thread that generated an event.
+    pub name: Option<String>,
+
+    /// The path to the executable the process is running.
+    pub executable: Option<String>,
+
+    /// The arguments the process was run with, separated by spaces.
+    pub command_line: Option<String>,
+
+    /// The time at which the process started, in nanoseconds since boot including the time spent suspended.
+    pub start_time: Option<u64>,
+}
+
+impl ProcessInfo {
+    /// Reads the information about a process from /proc. Anything that can't be read is left empty.
+    pub fn read(pid: u32) -> Self {
+        let name = fs::read_to_string(format!("/proc/{pid}/comm")).ok().map(|name| name.trim_end().to_string());
+        let executable = fs::read_link(format!("/proc/{pid}/exe")).ok().map(|path| path.to_string_lossy().into_owned());
+        let command_line = fs::read(format!("/proc/{pid}/cmdline")).ok().and_then(|arguments| {
+            let arguments: Vec<_> = arguments
+                .split(|c| *c == 0)
+                .filter(|argument| !argument.is_empty())
+                .map(String::from_utf8_lossy)
+                .collect();
+            // Kernel threads have no arguments at all.
+            (!arguments.is_empty()).then(|| arguments.join(" "))
+        });
+        // The start time is the 22nd field, in clock ticks since boot.
+        let start_time = stat_fields(pid).and_then(|fields| fields.get(19)?.parse::<u64>().ok()).map(|ticks| {
+            let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as u64;
+            ticks * 1_000_000_000 / ticks_per_second.max(1)
+        });
+        Self { name, executable, command_line, start_time }
+    }
+}
+
+/// A cache of the information about processes, so /proc isn't read for every event.
+///
+/// Processes are identified by their pid along with the time they started, as read by the eBPF programs, so the
+/// information about a process isn't reused for a later one that got the same pid.
+pub struct ProcessCache {
+    processes: LruCache<(u32, u64), ProcessInfo>,
+}
+
+impl ProcessCache {
+    pub fn new(size: NonZeroUsize) -> Self {
+        Self { processes: LruCache::new(size) }
+    }
+
+    /// Gets the information about a process, reading it from /proc if it's not cached.
+    pub fn get(&mut self, pid: u32, start_time: u64) -> &ProcessInfo {
+        self.processes.get_or_insert((pid, start_time), || ProcessInfo::read(pid))
+    }
+
+    /// Replaces the information about a process, e.g. because it executed a different program.
+    pub fn insert(&mut self, pid: u32, start_time: u64, info: ProcessInfo) {
+        self.processes.put((pid, start_time), info);
+    }
+}
+
+/// Reads the fields in /proc/<pid>/stat that come after the process name, starting at the process state.
+fn stat_fields(pid: u32) -> Option<Vec<String>> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The process name is wrapped in parentheses and can contain anything, including spaces and parentheses.
+    let (_, fields) = stat.rsplit_once(')')?;
+    Some(fields.split_whitespace().map(String::from).collect())
+}
@@ -1,58 +1,569 @@
-use crate::{bpf::BpfEvent, errno::Errno};
+use crate::{
+    bpf::BpfEvent,
+    capture::RecordedMachine,
+    clock::{monotonic_now, Clock},
+    container::{net_namespace, CgroupResolver},
+    errno::Errno,
+    event::{
+        Event, EventDetails, ExecEvent, FlowEvent, Identity, Process, ProcessDetails, StateChangeEvent, SyscallAddress,
+        SyscallEvent,
+    },
+    expression::FilterExpression,
+    flows::{Flow, FlowOwner, FlowTracker},
+    latency::LatencyTracker,
+    listeners::{ListenerChange, ListenerTable},
+    options::SocketOption,
+    process::{ProcessCache, ProcessInfo},
+    sink::EventSink,
+    socket::SocketKind,
+    summary::{KernelSummary, SummaryTable},
+};
 use anyhow::anyhow;
 use enum_primitive_derive::Primitive;
 use log::warn;
+use lru::LruCache;
 use num_traits::FromPrimitive;
-use sockwho_common::{AddressFamily, SockaddrEvent, SocketStateEvent, Syscall};
+use serde::Serialize;
+use sockwho_common::{
+    AddressFamily, ProcessExecEvent, SockaddrEvent, SocketCallEvent, SocketCreatedEvent, SocketOptionEvent,
+    SocketStateEvent, Syscall, OPTION_VALUE_MAX,
+};
 use std::{
-    fmt::{self},
+    collections::{BTreeMap, HashMap},
+    io::{self, stdout, IsTerminal, Write},
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    num::NonZeroUsize,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    select,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Notify,
+    },
+    time::{interval, interval_at, Instant},
 };
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+const PROCESS_CACHE_SIZE: usize = 4096;
+// The same size as the map of sockets in the eBPF programs.
+const SOCKET_CACHE_SIZE: usize = 65536;
 
 pub struct EventProcessorConfig {
     pub channel_size: usize,
+    pub mode: OutputMode,
+
+    /// Where events are written to when outputting events. Every event is written to all of them.
+    pub sinks: Vec<Box<dyn EventSink>>,
+
+    /// How long events are held for before being processed, so events that were delivered out of order (e.g. because
+    /// they happened on different CPUs) can be processed in the order they happened.
+    pub reorder_window: Duration,
+
+    /// Whether to display the executable, command line and start time of the process behind every event.
+    pub enrich: bool,
+
+    /// Only process the events generated by the container with this id, or id prefix, or the systemd unit with this
+    /// name.
+    pub container: Option<String>,
+
+    /// Only display the events that match this expression.
+    pub filter: Option<FilterExpression>,
+
+    /// How often to print the report in the modes that aggregate events, which is otherwise only printed once
+    /// processing stops.
+    pub report_interval: Option<Duration>,
+
+    /// The summary kept by the eBPF programs, when summarizing in the kernel.
+    pub kernel_summary: Option<KernelSummary>,
+
+    /// Converts the timestamps in events into wall-clock time, using the boot time of the machine they were generated
+    /// on.
+    pub clock: Clock,
+
+    /// The machine the events were recorded on, if they're being replayed from a capture rather than traced live.
+    /// Replayed events are processed as fast as they arrive, and nothing is looked up about the processes behind them
+    /// on this machine given they may have been generated on a different one.
+    pub replay: Option<RecordedMachine>,
+}
+
+/// What the processor outputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Print a line for every event.
+    Events,
+
+    /// Print the table of listening sockets every time it changes.
+    Listeners,
+
+    /// Print a summary of every TCP connection once it's closed.
+    Flows,
+
+    /// Print histograms of how long TCP handshakes take.
+    Latency,
+
+    /// Print how many times every process made every syscall, by address and result.
+    Summary,
 }
 
 pub struct EventProcessor {
     sender: Sender<BpfEvent>,
     receiver: Receiver<BpfEvent>,
+    mode: OutputMode,
+    sinks: Vec<Box<dyn EventSink>>,
+    reorder_window: Duration,
+    clock: Clock,
+    sockets: LruCache<(u32, u32), TrackedSocket>,
+    listeners: ListenerTable,
+    flows: FlowTracker,
+    latency: LatencyTracker,
+    summary: SummaryTable,
+    kernel_summary: Option<KernelSummary>,
+    report_interval: Option<Duration>,
+    replay: bool,
+    stop: Arc<Notify>,
+    // Only set when enriching events with process information.
+    processes: Option<ProcessCache>,
+    // The process information read as soon as an exec event arrived, indexed by pid and timestamp.
+    exec_snapshots: HashMap<(u32, u64), ProcessInfo>,
+    cgroups: CgroupResolver,
+    container: Option<String>,
+    // Only the namespaces other than our own are displayed.
+    host_net_namespace: Option<u64>,
+    filter: Option<FilterExpression>,
+    // The first error the sinks failed with, e.g. because the reader of a pipe went away. Nothing else is written once
+    // it's set and processing stops.
+    write_error: Option<anyhow::Error>,
+    // Whether the event being processed matches the filter. Events that don't are still processed so the state kept
+    // for sockets is complete, they're just not displayed.
+    visible: bool,
+}
+
+/// The state kept for a socket until it's closed.
+#[derive(Default)]
+struct TrackedSocket {
+    kind: Option<SocketKind>,
+    // The time the socket was created at, only known if that happened while tracing.
+    created: Option<u64>,
+    // The address this socket was bound to, as requested by the process.
+    bound: Option<String>,
+    // The options that affect which addresses this socket can use, in the order they were first set.
+    options: Vec<SocketOption>,
 }
 
 impl EventProcessor {
     pub fn new(config: EventProcessorConfig) -> Self {
         let (sender, receiver) = channel(config.channel_size);
-        Self { sender, receiver }
+        Self {
+            sender,
+            receiver,
+            mode: config.mode,
+            sinks: config.sinks,
+            reorder_window: config.reorder_window,
+            clock: config.clock,
+            sockets: LruCache::new(NonZeroUsize::new(SOCKET_CACHE_SIZE).expect("empty cache")),
+            listeners: ListenerTable::default(),
+            flows: FlowTracker::default(),
+            latency: LatencyTracker::default(),
+            summary: SummaryTable::default(),
+            kernel_summary: config.kernel_summary,
+            report_interval: config.report_interval,
+            replay: config.replay.is_some(),
+            stop: Arc::new(Notify::new()),
+            processes: config
+                .enrich
+                .then(|| ProcessCache::new(NonZeroUsize::new(PROCESS_CACHE_SIZE).expect("empty cache"))),
+            exec_snapshots: HashMap::new(),
+            cgroups: match &config.replay {
+                Some(machine) => CgroupResolver::offline(machine.workloads.clone()),
+                None => CgroupResolver::new(),
+            },
+            container: config.container,
+            host_net_namespace: match &config.replay {
+                Some(machine) => machine.net_namespace,
+                None => net_namespace(std::process::id()),
+            },
+            filter: config.filter,
+            write_error: None,
+            visible: true,
+        }
     }
 
     pub fn sender(&self) -> Sender<BpfEvent> {
         self.sender.clone()
     }
 
-    pub async fn run(mut self) {
-        while let Some(event) = self.receiver.recv().await {
-            let result = match event {
-                BpfEvent::Sockaddr(event) => self.process_sockaddr_event(event),
-                BpfEvent::SocketState(event) => self.process_socket_state_event(event),
+    /// A handle that can be used to stop processing events. Once notified, the events that were already received are
+    /// processed, the reports are printed and `run` returns.
+    pub fn stop_handle(&self) -> Arc<Notify> {
+        self.stop.clone()
+    }
+
+    /// Processes events until stopped or until the channel is closed, failing if the events can't be written.
+    pub async fn run(mut self) -> anyhow::Result<()> {
+        // Events waiting for the reorder window to pass, sorted by timestamp and then by arrival.
+        let mut pending = BTreeMap::new();
+        let mut sequence: u64 = 0;
+        // The time of the latest event received, which is the current time when replaying.
+        let mut latest = 0;
+        let mut ticker = interval(self.reorder_window.max(Duration::from_millis(10)));
+        let mut report_ticker = self.report_interval.map(|period| interval_at(Instant::now() + period, period));
+        let stop = self.stop.clone();
+        loop {
+            select! {
+                event = self.receiver.recv() => {
+                    let Some(event) = event else {
+                        break;
+                    };
+                    self.snapshot_exec(&event);
+                    latest = latest.max(event.timestamp());
+                    pending.insert((event.timestamp(), sequence), event);
+                    sequence += 1;
+                }
+                _ = ticker.tick() => (),
+                Some(_) = async { Some(report_ticker.as_mut()?.tick().await) }, if report_ticker.is_some() => {
+                    self.print_report();
+                }
+                _ = stop.notified() => break,
             };
-            if let Err(e) = result {
-                warn!("Failed to handle event: {e}");
+            let now = if self.replay { latest } else { monotonic_now() };
+            let deadline = now.saturating_sub(self.reorder_window.as_nanos() as u64);
+            while let Some(entry) = pending.first_entry() {
+                if entry.key().0 > deadline {
+                    break;
+                }
+                self.process_event(entry.remove());
+            }
+            self.expire_flows(deadline);
+            self.expire_handshakes(deadline);
+            if let Some(e) = self.write_error.take() {
+                return write_failed(e);
+            }
+        }
+        // Process the events that were received but not picked up yet as well.
+        while let Ok(event) = self.receiver.try_recv() {
+            pending.insert((event.timestamp(), sequence), event);
+            sequence += 1;
+        }
+        for (_, event) in pending {
+            self.process_event(event);
+        }
+        self.expire_flows(u64::MAX);
+        self.print_report();
+        if let Some(e) = self.write_error.take() {
+            return write_failed(e);
+        }
+        for sink in &mut self.sinks {
+            if let Err(e) = sink.flush() {
+                return write_failed(e.context("flushing events"));
+            }
+        }
+        Ok(())
+    }
+
+    fn process_event(&mut self, event: BpfEvent) {
+        if let Some(container) = &self.container {
+            let matches = self
+                .cgroups
+                .workload(event.cgroup_id(), event.pid())
+                .is_some_and(|workload| workload.matches(container));
+            if !matches {
+                return;
             }
         }
+        self.visible = self.filter.as_ref().is_none_or(|filter| filter.matches(&event));
+        let result = match event {
+            BpfEvent::Sockaddr(event) => self.process_sockaddr_event(event),
+            BpfEvent::SocketState(event) => self.process_socket_state_event(event),
+            BpfEvent::SocketCreated(event) => self.process_socket_created_event(event),
+            BpfEvent::SocketCall(event) => self.process_socket_call_event(event),
+            BpfEvent::SocketOption(event) => self.process_socket_option_event(event),
+            BpfEvent::ProcessExec(event) => self.process_exec_event(event),
+        };
+        if let Err(e) = result {
+            warn!("Failed to handle event: {e}");
+        }
+    }
+
+    /// Reads the information about a process that just executed a program as soon as the event arrives, given
+    /// short-lived processes may be gone by the time the event is processed.
+    fn snapshot_exec(&mut self, event: &BpfEvent) {
+        if let (BpfEvent::ProcessExec(event), Some(_)) = (event, &self.processes) {
+            self.exec_snapshots.insert((event.pid, event.timestamp), ProcessInfo::read(event.pid));
+        }
+    }
+
+    fn process_sockaddr_event(&mut self, event: SockaddrEvent) -> anyhow::Result<()> {
+        let SockaddrEvent {
+            pid,
+            fd,
+            address,
+            port,
+            family,
+            syscall,
+            local_port,
+            implicit_peer,
+            path_length,
+            local_address,
+            return_value,
+            command,
+            timestamp,
+            duration,
+            path,
+            tid,
+            uid,
+            gid,
+            ppid,
+            net_namespace,
+            cgroup_id,
+            start_time,
+        } = &event;
+        let command = parse_command(command);
+        let identity = self.identity(*pid, *tid, *ppid, *uid, *gid, *cgroup_id, *net_namespace);
+        if let Syscall::Accept = syscall {
+            if *return_value >= 0 {
+                // The accepted socket is of the same kind as the listening one.
+                let kind = self.sockets.get(&(*pid, *fd)).and_then(|socket| socket.kind.clone());
+                let socket = TrackedSocket { kind, created: Some(*timestamp), ..Default::default() };
+                self.sockets.put((*pid, *return_value as u32), socket);
+            }
+        }
+        let mut address = match family {
+            AddressFamily::Unix => Some(SyscallAddress {
+                family: *family,
+                address: None,
+                port: None,
+                path: Some(unix_path(&path[..(*path_length as usize).min(path.len())])),
+                local_address: None,
+                local_port: None,
+            }),
+            AddressFamily::Unspecified => None,
+            _ => Some(SyscallAddress {
+                family: *family,
+                address: Some(parse_address(family, address)?),
+                port: Some(port.to_be()),
+                path: None,
+                local_address: None,
+                local_port: None,
+            }),
+        };
+        let formatted_address = address.as_ref().map(ToString::to_string);
+        // getsockname(2) is how processes find out the address they were actually bound to (e.g. after binding to port
+        // 0), so show it next to the one they asked for.
+        let mut bound_address = None;
+        match (syscall, return_value, formatted_address) {
+            (Syscall::Bind, 0, Some(formatted_address)) => {
+                self.sockets.get_or_insert_mut((*pid, *fd), TrackedSocket::default).bound =
+                    Some(formatted_address.clone());
+                if self.mode == OutputMode::Listeners {
+                    self.listeners.bind(*pid, *fd, formatted_address, *family);
+                }
+            }
+            (Syscall::GetSockName, 0, Some(formatted_address)) => {
+                if let Some(bound) = self.sockets.get(&(*pid, *fd)).and_then(|socket| socket.bound.as_ref()) {
+                    if *bound != formatted_address {
+                        bound_address = Some(bound.clone());
+                    }
+                }
+            }
+            _ => (),
+        };
+        let options = match syscall {
+            Syscall::Bind | Syscall::Connect => self.socket_options(*pid, *fd),
+            _ => Vec::new(),
+        };
+        let endpoint = address.as_ref().and_then(|address| address.address.zip(address.port));
+        if let (OutputMode::Flows, Some((peer_address, peer_port))) = (self.mode, endpoint) {
+            match syscall {
+                Syscall::Connect => {
+                    let flow = self.flows.connected(*pid, peer_address, peer_port, *fd, *return_value, self.visible);
+                    if let Some(flow) = flow {
+                        self.emit_flow(flow);
+                    }
+                }
+                Syscall::Accept if *return_value >= 0 => {
+                    let owner = FlowOwner {
+                        pid: *pid,
+                        start_time: *start_time,
+                        command: command.clone(),
+                        identity: identity.clone(),
+                    };
+                    let fd = *return_value as u32;
+                    self.flows.accepted(*net_namespace, peer_address, peer_port, fd, owner, self.visible);
+                }
+                _ => (),
+            };
+        }
+        if let (Some(address), true) = (&mut address, *implicit_peer) {
+            address.local_address = Some(parse_address(family, local_address)?);
+            address.local_port = Some(local_port.to_be());
+        }
+        let event = SyscallEvent {
+            syscall: syscall_name(syscall),
+            fd: Some(*fd),
+            socket_kind: self.socket_kind(*pid, *fd),
+            address,
+            return_value: *return_value,
+            errno: errno(*return_value),
+            duration: *duration,
+            bound_address,
+            options,
+            ..Default::default()
+        };
+        self.emit(*timestamp, *pid, *start_time, command, Some(identity), EventDetails::Syscall(Box::new(event)));
+        Ok(())
+    }
+
+    fn process_socket_call_event(&mut self, event: SocketCallEvent) -> anyhow::Result<()> {
+        let SocketCallEvent {
+            pid,
+            fd,
+            syscall,
+            tid,
+            uid,
+            gid,
+            ppid,
+            net_namespace,
+            argument,
+            command,
+            return_value,
+            timestamp,
+            duration,
+            cgroup_id,
+            start_time,
+            ..
+        } = event;
+        let command = parse_command(&command);
+        if let (Syscall::Listen, 0, OutputMode::Listeners) = (syscall, return_value, self.mode) {
+            let change = self.listeners.listen(pid, tid, fd, argument, &command);
+            self.print_listeners(change);
+        }
+        let mut event = SyscallEvent {
+            syscall: syscall_name(&syscall),
+            fd: Some(fd),
+            socket_kind: self.socket_kind(pid, fd),
+            argument: Some(argument),
+            return_value,
+            errno: errno(return_value),
+            duration,
+            ..Default::default()
+        };
+        if let Syscall::Close = syscall {
+            // close(2) takes no argument other than the file descriptor.
+            event.argument = None;
+            // The file descriptor is released even if close(2) fails, so it can now be reused by a different socket.
+            if let Some(created) = self.sockets.pop(&(pid, fd)).and_then(|socket| socket.created) {
+                event.lifetime = Some(timestamp.saturating_sub(created));
+            }
+            if self.mode == OutputMode::Listeners {
+                let change = self.listeners.closed(pid, fd);
+                self.print_listeners(change);
+            }
+        }
+        let identity = self.identity(pid, tid, ppid, uid, gid, cgroup_id, net_namespace);
+        self.emit(timestamp, pid, start_time, command, Some(identity), EventDetails::Syscall(Box::new(event)));
+        Ok(())
+    }
+
+    fn process_socket_created_event(&mut self, event: SocketCreatedEvent) -> anyhow::Result<()> {
+        let SocketCreatedEvent {
+            pid,
+            domain,
+            socket_type,
+            protocol,
+            tid,
+            uid,
+            gid,
+            ppid,
+            net_namespace,
+            command,
+            return_value,
+            timestamp,
+            duration,
+            cgroup_id,
+            start_time,
+            ..
+        } = event;
+        let command = parse_command(&command);
+        let kind = SocketKind { domain, socket_type, protocol };
+        let identity = self.identity(pid, tid, ppid, uid, gid, cgroup_id, net_namespace);
+        let event = SyscallEvent {
+            syscall: "socket",
+            socket: Some(kind.clone()),
+            return_value,
+            errno: errno(return_value),
+            duration,
+            ..Default::default()
+        };
+        self.emit(timestamp, pid, start_time, command, Some(identity), EventDetails::Syscall(Box::new(event)));
+        if return_value >= 0 {
+            let socket = TrackedSocket { kind: Some(kind), created: Some(timestamp), ..Default::default() };
+            self.sockets.put((pid, return_value as u32), socket);
+        }
+        Ok(())
+    }
+
+    fn process_socket_option_event(&mut self, event: SocketOptionEvent) -> anyhow::Result<()> {
+        let SocketOptionEvent {
+            pid,
+            fd,
+            level,
+            name,
+            value_length,
+            value,
+            command,
+            tid,
+            uid,
+            gid,
+            ppid,
+            net_namespace,
+            return_value,
+            timestamp,
+            duration,
+            cgroup_id,
+            start_time,
+        } = event;
+        let command = parse_command(&command);
+        let identity = self.identity(pid, tid, ppid, uid, gid, cgroup_id, net_namespace);
+        let captured = (value_length as usize).min(OPTION_VALUE_MAX);
+        let option = SocketOption::new(level, name, &value[..captured], value_length);
+        let event = SyscallEvent {
+            syscall: "set_sock_opt",
+            fd: Some(fd),
+            socket_kind: self.socket_kind(pid, fd),
+            option: Some(option.clone()),
+            return_value,
+            errno: errno(return_value),
+            duration,
+            ..Default::default()
+        };
+        self.emit(timestamp, pid, start_time, command, Some(identity), EventDetails::Syscall(Box::new(event)));
+        if return_value == 0 && option.affects_addressing() {
+            let options = &mut self.sockets.get_or_insert_mut((pid, fd), TrackedSocket::default).options;
+            match options.iter_mut().find(|existing| existing.level == level && existing.name == name) {
+                Some(existing) => *existing = option,
+                None => options.push(option),
+            };
+        }
+        Ok(())
     }
 
-    fn process_sockaddr_event(&self, event: SockaddrEvent) -> anyhow::Result<()> {
-        let SockaddrEvent { pid, fd, address, port, family, syscall, return_value, command, .. } = &event;
-        let command = String::from_utf8_lossy(command);
-        let syscall = syscall_name(syscall);
-        let address = parse_address(family, address);
-        let port = port.to_be();
-        let return_value = ReturnValueDisplay(*return_value);
-        println!("{command}/{pid}/{fd} syscall::{syscall}({address}:{port}) = {return_value}");
+    fn process_exec_event(&mut self, event: ProcessExecEvent) -> anyhow::Result<()> {
+        let ProcessExecEvent { pid, path_length, timestamp, start_time, command, path, .. } = event;
+        let command = parse_command(&command);
+        let path = &path[..(path_length as usize).min(path.len())];
+        let end = path.iter().position(|c| *c == 0).unwrap_or(path.len());
+        let path = String::from_utf8_lossy(&path[..end]).into_owned();
+        if let Some(processes) = &mut self.processes {
+            let mut info = self.exec_snapshots.remove(&(pid, timestamp)).unwrap_or_default();
+            info.executable.get_or_insert_with(|| path.clone());
+            processes.insert(pid, start_time, info);
+        }
+        self.emit(timestamp, pid, start_time, command, None, EventDetails::Exec(ExecEvent { path }));
         Ok(())
     }
 
-    fn process_socket_state_event(&self, event: SocketStateEvent) -> anyhow::Result<()> {
+    fn process_socket_state_event(&mut self, event: SocketStateEvent) -> anyhow::Result<()> {
         let SocketStateEvent {
             src_port,
             dst_port,
@@ -64,50 +575,281 @@ impl EventProcessor {
             src_address,
             dst_address,
             command,
+            tid,
+            uid,
+            gid,
+            ppid,
+            net_namespace,
+            timestamp,
+            cgroup_id,
+            start_time,
         } = &event;
-        let command = String::from_utf8_lossy(command);
-        let src_address = parse_address(family, src_address);
-        let dst_address = parse_address(family, dst_address);
+        let command = parse_command(command);
+        let identity = self.identity(*pid, *tid, *ppid, *uid, *gid, *cgroup_id, *net_namespace);
+        let src_address = parse_address(family, src_address)?;
+        let dst_address = parse_address(family, dst_address)?;
         let old_state = TcpState::from_u32(*old_state).ok_or_else(|| anyhow!("invalid old state"))?;
         let new_state = TcpState::from_u32(*new_state).ok_or_else(|| anyhow!("invalid new state"))?;
-        println!(
-            "{command}/{pid} socket::set_state({src_address}:{src_port} <-> {dst_address}:{dst_port}) {old_state:?} -> {new_state:?}"
-        );
+        if self.mode == OutputMode::Listeners {
+            let address = format!("{src_address}:{src_port}");
+            let change = self.listeners.state_changed(*pid, *tid, &command, address, &old_state, &new_state);
+            self.print_listeners(change);
+        }
+        let event = StateChangeEvent {
+            family: *family,
+            source_address: src_address,
+            source_port: *src_port,
+            destination_address: dst_address,
+            destination_port: *dst_port,
+            old_state,
+            new_state,
+        };
+        if self.mode == OutputMode::Latency {
+            self.latency.state_changed(&event, *net_namespace, *timestamp, &command, self.visible);
+        }
+        if self.mode == OutputMode::Flows {
+            let owner =
+                FlowOwner { pid: *pid, start_time: *start_time, command: command.clone(), identity: identity.clone() };
+            let flow = self.flows.state_changed(&event, *net_namespace, *timestamp, owner, self.visible);
+            if let Some(flow) = flow {
+                self.emit_flow(flow);
+            }
+        }
+        self.emit(*timestamp, *pid, *start_time, command, Some(identity), EventDetails::StateChange(event));
         Ok(())
     }
+
+    /// The thread, parent process, user and workload behind an event.
+    #[allow(clippy::too_many_arguments)]
+    fn identity(
+        &mut self,
+        pid: u32,
+        tid: u32,
+        ppid: u32,
+        uid: u32,
+        gid: u32,
+        cgroup_id: u64,
+        net_namespace: u32,
+    ) -> Identity {
+        // The parent and the network namespace are read by the eBPF programs, and are 0 if they couldn't.
+        let ppid = (ppid != 0).then_some(ppid);
+        let workload = self.cgroups.workload(cgroup_id, pid);
+        let net_namespace = Some(net_namespace as u64)
+            .filter(|namespace| *namespace != 0 && Some(*namespace) != self.host_net_namespace);
+        Identity { tid, ppid, uid, gid, workload, net_namespace }
+    }
+
+    /// The information about a process read from /proc, if enabled.
+    fn process_details(&mut self, pid: u32, start_time: u64) -> Option<ProcessDetails> {
+        let ProcessInfo { name, executable, command_line, start_time } =
+            self.processes.as_mut()?.get(pid, start_time).clone();
+        let start_time = start_time.map(|start_time| self.clock.wall_clock_since_boot(start_time));
+        Some(ProcessDetails { name, executable, command_line, start_time })
+    }
+
+    /// The name of the kind of socket behind a file descriptor, if known.
+    fn socket_kind(&self, pid: u32, fd: u32) -> Option<String> {
+        self.sockets.peek(&(pid, fd)).and_then(|socket| socket.kind.as_ref()).map(SocketKind::name)
+    }
+
+    /// The options set on a socket that affect addressing.
+    fn socket_options(&self, pid: u32, fd: u32) -> Vec<SocketOption> {
+        self.sockets.peek(&(pid, fd)).map(|socket| socket.options.clone()).unwrap_or_default()
+    }
+
+    /// Outputs an event, made by the process with the given pid and start time, if it matched the filter.
+    fn emit(
+        &mut self,
+        timestamp: u64,
+        pid: u32,
+        start_time: u64,
+        comm: String,
+        identity: Option<Identity>,
+        details: EventDetails,
+    ) {
+        if let (OutputMode::Summary, true, EventDetails::Syscall(event)) = (self.mode, self.visible, &details) {
+            self.summary.record(&comm, event, self.clock.wall_clock(timestamp));
+        }
+        if self.mode != OutputMode::Events || !self.visible {
+            return;
+        }
+        let info = self.process_details(pid, start_time);
+        let process = Process { pid, comm, identity, info };
+        let event = Event { timestamp: self.clock.wall_clock(timestamp), process, details };
+        self.write(&event);
+    }
+
+    /// Outputs the summary of a connection that was closed, if any of its events matched the filter.
+    fn emit_flow(&mut self, flow: Flow) {
+        if !flow.visible {
+            return;
+        }
+        let handshake = flow.handshake();
+        let Flow { tuple, family, direction, owner, fd, connect_result, opened, closed, close_reason, .. } = flow;
+        let closed = closed.unwrap_or(opened);
+        let details = FlowEvent {
+            family,
+            direction,
+            fd,
+            local_address: tuple.local_address,
+            local_port: tuple.local_port,
+            remote_address: tuple.remote_address,
+            remote_port: tuple.remote_port,
+            connect_result,
+            connect_errno: connect_result.and_then(errno),
+            handshake,
+            lifetime: closed.saturating_sub(opened),
+            close_reason,
+        };
+        let info = self.process_details(owner.pid, owner.start_time);
+        let process = Process { pid: owner.pid, comm: owner.command, identity: Some(owner.identity), info };
+        let event = Event { timestamp: self.clock.wall_clock(closed), process, details: EventDetails::Flow(details) };
+        self.write(&event);
+    }
+
+    /// Outputs the connections that were closed before the connect(2) call behind them was seen, if it's taking too
+    /// long.
+    fn expire_flows(&mut self, timestamp: u64) {
+        if self.mode != OutputMode::Flows {
+            return;
+        }
+        for flow in self.flows.expire(timestamp) {
+            self.emit_flow(flow);
+        }
+    }
+
+    /// Forgets the handshakes in progress that should have finished by now, whose end must have been missed.
+    fn expire_handshakes(&mut self, timestamp: u64) {
+        if self.mode == OutputMode::Latency {
+            self.latency.expire(timestamp);
+        }
+    }
+
+    fn write(&mut self, event: &Event) {
+        if self.write_error.is_some() {
+            return;
+        }
+        for sink in &mut self.sinks {
+            if let Err(e) = sink.write(event) {
+                self.write_error = Some(e.context("writing events"));
+                return;
+            }
+        }
+    }
+
+    /// Prints the report for the modes that aggregate events, if there's anything to report, and starts over.
+    fn print_report(&mut self) {
+        if self.mode == OutputMode::Latency && !self.latency.is_empty() {
+            self.print(&format!("{}\n", self.latency));
+            self.latency.clear();
+        }
+        if self.mode != OutputMode::Summary {
+            return;
+        }
+        if let Some(kernel_summary) = &mut self.kernel_summary {
+            if let Err(e) = kernel_summary.drain(&mut self.summary, &self.clock) {
+                warn!("Failed to read the kernel's summary: {e}");
+            }
+        }
+        if !self.summary.is_empty() {
+            self.print(&format!("{}\n", self.summary));
+            self.summary.clear();
+        }
+    }
+
+    fn print_listeners(&mut self, change: Option<ListenerChange>) {
+        let Some(change) = change else {
+            return;
+        };
+        if stdout().is_terminal() {
+            // Redraw the whole table, followed by the last change.
+            self.print(&format!("\x1B[2J\x1B[H{}\n{change}\n", self.listeners));
+        } else {
+            self.print(&format!("{change}\n"));
+        }
+    }
+
+    /// Writes the output of the modes that don't print events, which doesn't go through the sinks. Failing to is
+    /// handled the same way as failing to write events.
+    fn print(&mut self, output: &str) {
+        if self.write_error.is_some() {
+            return;
+        }
+        let mut stdout = stdout().lock();
+        if let Err(e) = stdout.write_all(output.as_bytes()).and_then(|_| stdout.flush()) {
+            self.write_error = Some(anyhow::Error::new(e).context("printing"));
+        }
+    }
+}
+
+/// Stops processing after the events couldn't be written. That's not an error if it's because the reader of our output
+/// went away, e.g. when piped into head(1).
+fn write_failed(error: anyhow::Error) -> anyhow::Result<()> {
+    let broken_pipe =
+        error.chain().filter_map(|e| e.downcast_ref::<io::Error>()).any(|e| e.kind() == io::ErrorKind::BrokenPipe);
+    if broken_pipe {
+        Ok(())
+    } else {
+        Err(error)
+    }
+}
+
+/// Converts a NUL padded process name into a string.
+pub(crate) fn parse_command(command: &[u8; 16]) -> String {
+    let end = command.iter().position(|c| *c == 0).unwrap_or(command.len());
+    String::from_utf8_lossy(&command[..end]).into_owned()
 }
 
-fn parse_address(family: &AddressFamily, address: &[u8; 16]) -> IpAddr {
+pub(crate) fn parse_address(family: &AddressFamily, address: &[u8; 16]) -> anyhow::Result<IpAddr> {
     match family {
-        AddressFamily::Ipv4 => IpAddr::from(Ipv4Addr::from([address[0], address[1], address[2], address[3]])),
-        AddressFamily::Ipv6 => IpAddr::from(Ipv6Addr::from(*address)),
+        AddressFamily::Ipv4 => Ok(IpAddr::from(Ipv4Addr::from([address[0], address[1], address[2], address[3]]))),
+        AddressFamily::Ipv6 => Ok(IpAddr::from(Ipv6Addr::from(*address))),
+        AddressFamily::Unix => Err(anyhow!("unix sockets don't have an IP address")),
+        AddressFamily::Unspecified => Err(anyhow!("no address is known")),
     }
 }
 
-fn syscall_name(syscall: &Syscall) -> &'static str {
+/// Formats the path of a Unix domain socket the same way ss(8) does.
+pub(crate) fn unix_path(path: &[u8]) -> String {
+    match path.first() {
+        // Unnamed sockets (e.g. the ones created by socketpair(2)) have no path at all.
+        None => "unnamed".into(),
+        // Abstract socket names start with a NUL byte and can contain NULs anywhere.
+        Some(0) => path.iter().map(|c| if *c == 0 { '@' } else { *c as char }).collect(),
+        Some(_) => {
+            let end = path.iter().position(|c| *c == 0).unwrap_or(path.len());
+            String::from_utf8_lossy(&path[..end]).into_owned()
+        }
+    }
+}
+
+pub(crate) fn syscall_name(syscall: &Syscall) -> &'static str {
     use Syscall::*;
     match syscall {
         Bind => "bind",
         Connect => "connect",
         RecvFrom => "recv_from",
         SendTo => "send_to",
+        Accept => "accept",
+        SendMsg => "send_msg",
+        RecvMsg => "recv_msg",
+        SendMmsg => "send_mmsg",
+        RecvMmsg => "recv_mmsg",
+        Listen => "listen",
+        Shutdown => "shutdown",
+        Close => "close",
+        GetSockName => "get_sock_name",
+        GetPeerName => "get_peer_name",
     }
 }
 
-struct ReturnValueDisplay(i64);
-
-impl fmt::Display for ReturnValueDisplay {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)?;
-        if let Some(errno) = Errno::from_i64(-self.0) {
-            write!(f, " [{:?}]", errno)?;
-        }
-        Ok(())
-    }
+/// The error a syscall failed with, given its return value.
+fn errno(return_value: i64) -> Option<Errno> {
+    return_value.checked_neg().and_then(Errno::from_i64)
 }
 
 /// The state of a TCP connection.
-#[derive(Clone, Debug, Primitive, PartialEq, Eq)]
+#[derive(Clone, Debug, Primitive, PartialEq, Eq, Serialize)]
 pub enum TcpState {
     Established = 1,
     SynSent = 2,
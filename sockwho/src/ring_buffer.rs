@@ -0,0 +1,183 @@
+use anyhow::{anyhow, Context};
+use object::{Object, ObjectSection, ObjectSymbol};
+use std::{
+    ffi::CString,
+    io,
+    mem::size_of,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    path::Path,
+    ptr, slice,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
+use tokio::io::{unix::AsyncFd, Interest};
+
+// aya doesn't support ring buffers yet so these are all talked to directly through the bpf(2) syscall.
+const BPF_MAP_CREATE: libc::c_long = 0;
+const BPF_OBJ_GET: libc::c_long = 7;
+const BPF_MAP_TYPE_ARRAY: u32 = 2;
+const BPF_MAP_TYPE_RINGBUF: u32 = 27;
+const BPF_RINGBUF_BUSY_BIT: u32 = 1 << 31;
+const BPF_RINGBUF_DISCARD_BIT: u32 = 1 << 30;
+const BPF_RINGBUF_HEADER_SIZE: usize = 8;
+
+#[repr(C)]
+struct MapCreateAttributes {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+}
+
+#[repr(C)]
+struct ObjectAttributes {
+    pathname: u64,
+    bpf_fd: u32,
+    file_flags: u32,
+}
+
+/// A BPF ring buffer shared by the eBPF programs and us.
+pub struct RingBuffer {
+    fd: AsyncFd<OwnedFd>,
+    // The page that holds the position we've consumed up to.
+    consumer: *mut u8,
+    // The page that holds the position the kernel has produced up to, followed by the data, which is mapped twice in a
+    // row so records that wrap around the end of the buffer can be read in one go.
+    producer: *const u8,
+    size: usize,
+    page_size: usize,
+}
+
+// The mapped pages are only accessed through `&mut self`.
+unsafe impl Send for RingBuffer {}
+
+impl RingBuffer {
+    /// Opens a ring buffer of the given size that was pinned at a path.
+    pub fn open_pinned(path: &Path, size: usize) -> anyhow::Result<Self> {
+        let fd = get_pinned_object(path).context("opening pinned ring buffer")?;
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let consumer = map_memory(&fd, page_size, libc::PROT_READ | libc::PROT_WRITE, 0)?;
+        let producer = match map_memory(&fd, page_size + 2 * size, libc::PROT_READ, page_size) {
+            Ok(producer) => producer,
+            Err(e) => {
+                unsafe { libc::munmap(consumer as *mut libc::c_void, page_size) };
+                return Err(e);
+            }
+        };
+        // The descriptor is owned by the AsyncFd so it stays open for as long as it's registered. Its replacement needs a
+        // newer tokio than the one we support.
+        #[allow(deprecated)]
+        let fd = AsyncFd::with_interest(fd, Interest::READABLE)?;
+        Ok(Self { fd, consumer, producer, size, page_size })
+    }
+
+    /// Waits until there are records available and hands every one of them to the given function.
+    pub async fn read_records<F: FnMut(&[u8])>(&mut self, mut process: F) -> io::Result<()> {
+        let mut guard = self.fd.readable().await?;
+        // Clear the readiness before consuming so records written in the meantime wake us up again.
+        guard.clear_ready();
+        drop(guard);
+
+        let consumer_position = unsafe { &*(self.consumer as *const AtomicU64) };
+        let producer_position = unsafe { &*(self.producer as *const AtomicU64) };
+        let data = unsafe { self.producer.add(self.page_size) };
+        let mut position = consumer_position.load(Ordering::Acquire);
+        while position < producer_position.load(Ordering::Acquire) {
+            let header = unsafe { data.add(position as usize & (self.size - 1)) };
+            let length = unsafe { &*(header as *const AtomicU32) }.load(Ordering::Acquire);
+            if length & BPF_RINGBUF_BUSY_BIT != 0 {
+                // This record is still being written, it'll be read on the next wake up.
+                break;
+            }
+            let record_length = (length & !(BPF_RINGBUF_BUSY_BIT | BPF_RINGBUF_DISCARD_BIT)) as usize;
+            if length & BPF_RINGBUF_DISCARD_BIT == 0 {
+                let record = unsafe { slice::from_raw_parts(header.add(BPF_RINGBUF_HEADER_SIZE), record_length) };
+                process(record);
+            }
+            position += (record_length + BPF_RINGBUF_HEADER_SIZE).next_multiple_of(8) as u64;
+            consumer_position.store(position, Ordering::Release);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RingBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.consumer as *mut libc::c_void, self.page_size);
+            libc::munmap(self.producer as *mut libc::c_void, self.page_size + 2 * self.size);
+        }
+    }
+}
+
+/// Checks whether the running kernel supports ring buffers.
+pub fn is_supported() -> bool {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u32;
+    let attributes = MapCreateAttributes {
+        map_type: BPF_MAP_TYPE_RINGBUF,
+        key_size: 0,
+        value_size: 0,
+        max_entries: page_size,
+        map_flags: 0,
+    };
+    create_map(&attributes).is_ok()
+}
+
+/// Turns the definition of a ring buffer in an eBPF object into a tiny placeholder map that isn't pinned.
+///
+/// Loading the eBPF programs creates every map they define, so this lets them be loaded on kernels that don't support
+/// ring buffers. The programs never use the ring buffer in that case so the verifier skips the calls that need one.
+pub fn replace_with_placeholder(object: &[u8], name: &str) -> anyhow::Result<Vec<u8>> {
+    let file = object::File::parse(object).context("parsing eBPF object")?;
+    let symbol =
+        file.symbols().find(|symbol| symbol.name() == Ok(name)).ok_or_else(|| anyhow!("map {name} not found"))?;
+    let section = symbol.section_index().and_then(|index| file.section_by_index(index).ok());
+    let (section_offset, _) =
+        section.and_then(|section| section.file_range()).ok_or_else(|| anyhow!("map {name} has no data"))?;
+    // The definition is a bpf_map_def: its type, key size, value size, max entries, flags, id and pinning.
+    let offset = (section_offset + symbol.address()) as usize;
+    let definition = [BPF_MAP_TYPE_ARRAY, 4, 4, 1, 0, 0, 0];
+    let mut object = object.to_vec();
+    let target =
+        object.get_mut(offset..offset + 4 * definition.len()).ok_or_else(|| anyhow!("map {name} is truncated"))?;
+    for (bytes, value) in target.chunks_exact_mut(4).zip(definition) {
+        // eBPF objects are always little endian, given they're built for bpfel.
+        bytes.copy_from_slice(&value.to_le_bytes());
+    }
+    Ok(object)
+}
+
+fn create_map(attributes: &MapCreateAttributes) -> io::Result<OwnedFd> {
+    let fd = bpf(BPF_MAP_CREATE, attributes)?;
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+fn get_pinned_object(path: &Path) -> anyhow::Result<OwnedFd> {
+    let path = path_to_cstring(path)?;
+    let attributes = ObjectAttributes { pathname: path.as_ptr() as u64, bpf_fd: 0, file_flags: 0 };
+    let fd = bpf(BPF_OBJ_GET, &attributes)?;
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+fn bpf<T>(command: libc::c_long, attributes: &T) -> io::Result<libc::c_long> {
+    let result = unsafe { libc::syscall(libc::SYS_bpf, command, attributes as *const T, size_of::<T>()) };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+fn map_memory(fd: &OwnedFd, length: usize, protection: libc::c_int, offset: usize) -> anyhow::Result<*mut u8> {
+    let address = unsafe {
+        libc::mmap(ptr::null_mut(), length, protection, libc::MAP_SHARED, fd.as_raw_fd(), offset as libc::off_t)
+    };
+    if address == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error()).context("mapping ring buffer");
+    }
+    Ok(address as *mut u8)
+}
+
+fn path_to_cstring(path: &Path) -> anyhow::Result<CString> {
+    CString::new(path.to_string_lossy().into_owned()).context("invalid path")
+}
@@ -0,0 +1,237 @@
+use crate::{
+    errno::Errno,
+    event::{family_name, Event, EventDetails, ExecEvent, FlowEvent, Identity, StateChangeEvent},
+    flows::{CloseReason, Direction},
+    processor::TcpState,
+};
+use serde::Serialize;
+use std::{
+    io::{self, Write},
+    net::IpAddr,
+};
+
+/// Something that consumes the events processed by an `EventProcessor`, e.g. to display or store them.
+pub trait EventSink: Send {
+    /// Handles an event. Events are handed over in the order they happened.
+    fn write(&mut self, event: &Event) -> anyhow::Result<()>;
+
+    /// Flushes any events that are buffered. This is called once there are no more events.
+    fn flush(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes every event as a line of text, the same way strace does.
+pub struct TextSink<W> {
+    writer: W,
+}
+
+impl<W: Write + Send> TextSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl TextSink<io::Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl<W: Write + Send> EventSink for TextSink<W> {
+    fn write(&mut self, event: &Event) -> anyhow::Result<()> {
+        writeln!(self.writer, "{event}")?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Writes every event as a JSON object on its own line.
+pub struct JsonSink<W> {
+    writer: W,
+}
+
+impl<W: Write + Send> JsonSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl JsonSink<io::Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl<W: Write + Send> EventSink for JsonSink<W> {
+    fn write(&mut self, event: &Event) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut self.writer, event)?;
+        writeln!(self.writer)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Writes every event as a CSV record, preceded by a header.
+///
+/// All kinds of events share the same columns, so the ones that don't apply to an event are left empty. Socket state
+/// changes use the local columns for the source of the connection, and connections use the return value and errno
+/// columns for the ones connect(2) returned.
+pub struct CsvSink<W: Write> {
+    writer: csv::Writer<W>,
+}
+
+impl<W: Write + Send> CsvSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer: csv::Writer::from_writer(writer) }
+    }
+}
+
+impl CsvSink<io::Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+
+impl<W: Write + Send> EventSink for CsvSink<W> {
+    fn write(&mut self, event: &Event) -> anyhow::Result<()> {
+        self.writer.serialize(CsvRecord::from(event))?;
+        // Flush every record so the output can be followed as it's written.
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// An event flattened into the columns of a CSV record.
+#[derive(Default, Serialize)]
+struct CsvRecord<'a> {
+    timestamp: u64,
+    pid: u32,
+    comm: &'a str,
+    tid: Option<u32>,
+    ppid: Option<u32>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    workload: Option<String>,
+    net_namespace: Option<u64>,
+    event: &'static str,
+    syscall: Option<&'static str>,
+    fd: Option<u32>,
+    socket_kind: Option<&'a str>,
+    family: Option<&'static str>,
+    address: Option<IpAddr>,
+    port: Option<u16>,
+    local_address: Option<IpAddr>,
+    local_port: Option<u16>,
+    path: Option<&'a str>,
+    return_value: Option<i64>,
+    errno: Option<Errno>,
+    duration: Option<u64>,
+    old_state: Option<TcpState>,
+    new_state: Option<TcpState>,
+    direction: Option<Direction>,
+    handshake: Option<u64>,
+    lifetime: Option<u64>,
+    close_reason: Option<CloseReason>,
+}
+
+impl<'a> From<&'a Event> for CsvRecord<'a> {
+    fn from(event: &'a Event) -> Self {
+        let mut record = Self {
+            timestamp: event.timestamp,
+            pid: event.process.pid,
+            comm: &event.process.comm,
+            ..Default::default()
+        };
+        if let Some(Identity { tid, ppid, uid, gid, workload, net_namespace }) = &event.process.identity {
+            record.tid = Some(*tid);
+            record.ppid = *ppid;
+            record.uid = Some(*uid);
+            record.gid = Some(*gid);
+            record.workload = workload.as_ref().map(ToString::to_string);
+            record.net_namespace = *net_namespace;
+        }
+        match &event.details {
+            EventDetails::Syscall(syscall) => {
+                record.event = "syscall";
+                record.syscall = Some(syscall.syscall);
+                record.fd = syscall.fd;
+                record.socket_kind = syscall.socket_kind.as_deref();
+                if let Some(address) = &syscall.address {
+                    record.family = Some(family_name(&address.family));
+                    record.address = address.address;
+                    record.port = address.port;
+                    record.local_address = address.local_address;
+                    record.local_port = address.local_port;
+                    record.path = address.path.as_deref();
+                }
+                record.return_value = Some(syscall.return_value);
+                record.errno = syscall.errno;
+                record.duration = Some(syscall.duration);
+            }
+            EventDetails::StateChange(state) => {
+                let StateChangeEvent {
+                    family,
+                    source_address,
+                    source_port,
+                    destination_address,
+                    destination_port,
+                    old_state,
+                    new_state,
+                } = state;
+                record.event = "state_change";
+                record.family = Some(family_name(family));
+                record.address = Some(*destination_address);
+                record.port = Some(*destination_port);
+                record.local_address = Some(*source_address);
+                record.local_port = Some(*source_port);
+                record.old_state = Some(old_state.clone());
+                record.new_state = Some(new_state.clone());
+            }
+            EventDetails::Exec(ExecEvent { path }) => {
+                record.event = "exec";
+                record.path = Some(path);
+            }
+            EventDetails::Flow(flow) => {
+                let FlowEvent {
+                    family,
+                    direction,
+                    fd,
+                    local_address,
+                    local_port,
+                    remote_address,
+                    remote_port,
+                    connect_result,
+                    connect_errno,
+                    handshake,
+                    lifetime,
+                    close_reason,
+                } = flow;
+                record.event = "flow";
+                record.family = Some(family_name(family));
+                record.fd = *fd;
+                record.address = Some(*remote_address);
+                record.port = Some(*remote_port);
+                record.local_address = Some(*local_address);
+                record.local_port = Some(*local_port);
+                record.return_value = *connect_result;
+                record.errno = *connect_errno;
+                record.direction = Some(*direction);
+                record.handshake = *handshake;
+                record.lifetime = Some(*lifetime);
+                record.close_reason = Some(*close_reason);
+            }
+        };
+        record
+    }
+}
@@ -0,0 +1,103 @@
+use enum_primitive_derive::Primitive;
+use num_traits::FromPrimitive;
+use serde::Serialize;
+use std::fmt;
+
+// The flags that can be OR'd into a socket's type.
+const SOCK_NONBLOCK: u32 = 0o4000;
+const SOCK_CLOEXEC: u32 = 0o2000000;
+const SOCK_TYPE_MASK: u32 = 0xf;
+
+/// The kind of a socket, as requested when creating it via socket(2).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SocketKind {
+    pub domain: u32,
+    pub socket_type: u32,
+    pub protocol: u32,
+}
+
+impl SocketKind {
+    /// A short name that describes this socket, e.g. "tcp" or "unix_dgram".
+    pub fn name(&self) -> String {
+        use Protocol::*;
+        use SocketDomain::*;
+        use SocketType::*;
+        let domain = SocketDomain::from_u32(self.domain);
+        let socket_type = SocketType::from_u32(self.socket_type & SOCK_TYPE_MASK);
+        let protocol = Protocol::from_u32(self.protocol);
+        let name = match (domain, socket_type, protocol) {
+            (Some(AF_INET | AF_INET6), Some(SOCK_STREAM), Some(IPPROTO_IP | IPPROTO_TCP)) => "tcp",
+            (Some(AF_INET | AF_INET6), Some(SOCK_DGRAM), Some(IPPROTO_IP | IPPROTO_UDP)) => "udp",
+            (Some(AF_INET | AF_INET6), Some(SOCK_DGRAM), Some(IPPROTO_ICMP | IPPROTO_ICMPV6)) => "icmp",
+            (Some(AF_INET | AF_INET6), _, Some(IPPROTO_SCTP)) => "sctp",
+            (Some(AF_INET | AF_INET6), Some(SOCK_RAW), _) => "raw",
+            (Some(AF_UNIX), Some(SOCK_STREAM), _) => "unix_stream",
+            (Some(AF_UNIX), Some(SOCK_DGRAM), _) => "unix_dgram",
+            (Some(AF_UNIX), Some(SOCK_SEQPACKET), _) => "unix_seqpacket",
+            (Some(AF_NETLINK), _, _) => "netlink",
+            (Some(AF_PACKET), _, _) => "packet",
+            _ => return format!("{}/{}/{}", self.domain, self.socket_type & SOCK_TYPE_MASK, self.protocol),
+        };
+        name.into()
+    }
+}
+
+/// Displays the arguments used to create this socket the same way strace does.
+impl fmt::Display for SocketKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match SocketDomain::from_u32(self.domain) {
+            Some(domain) => write!(f, "{domain:?}, ")?,
+            None => write!(f, "{}, ", self.domain)?,
+        };
+        match SocketType::from_u32(self.socket_type & SOCK_TYPE_MASK) {
+            Some(socket_type) => write!(f, "{socket_type:?}")?,
+            None => write!(f, "{}", self.socket_type & SOCK_TYPE_MASK)?,
+        };
+        if self.socket_type & SOCK_NONBLOCK != 0 {
+            write!(f, "|SOCK_NONBLOCK")?;
+        }
+        if self.socket_type & SOCK_CLOEXEC != 0 {
+            write!(f, "|SOCK_CLOEXEC")?;
+        }
+        // Protocol numbers are only IP protocols for the inet domains.
+        let is_inet =
+            matches!(SocketDomain::from_u32(self.domain), Some(SocketDomain::AF_INET | SocketDomain::AF_INET6));
+        match Protocol::from_u32(self.protocol) {
+            Some(protocol) if is_inet => write!(f, ", {protocol:?}"),
+            _ => write!(f, ", {}", self.protocol),
+        }
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Primitive)]
+enum SocketDomain {
+    AF_UNIX = 1,
+    AF_INET = 2,
+    AF_INET6 = 10,
+    AF_NETLINK = 16,
+    AF_PACKET = 17,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Primitive)]
+enum SocketType {
+    SOCK_STREAM = 1,
+    SOCK_DGRAM = 2,
+    SOCK_RAW = 3,
+    SOCK_RDM = 4,
+    SOCK_SEQPACKET = 5,
+    SOCK_PACKET = 10,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Primitive)]
+enum Protocol {
+    IPPROTO_IP = 0,
+    IPPROTO_ICMP = 1,
+    IPPROTO_TCP = 6,
+    IPPROTO_UDP = 17,
+    IPPROTO_ICMPV6 = 58,
+    IPPROTO_SCTP = 132,
+    IPPROTO_RAW = 255,
+}
@@ -0,0 +1,313 @@
+use crate::{
+    clock::{Clock, TimestampDisplay},
+    errno::Errno,
+    event::{SyscallAddress, SyscallEvent},
+    processor::{parse_address, parse_command, syscall_name},
+};
+use aya::{
+    maps::{Array, HashMap as BpfHashMap, MapRefMut},
+    Bpf, Pod,
+};
+use num_traits::FromPrimitive;
+use sockwho_common::{SummaryKey, SummaryValue};
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+/// The values shared by the syscalls that are counted together.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SummaryGroup {
+    pub command: String,
+    pub syscall: &'static str,
+
+    /// The address and port, or the path for Unix domain sockets, if the syscall uses one.
+    pub address: Option<String>,
+
+    /// "ok" if the syscall succeeded, otherwise the error it failed with.
+    pub result: String,
+}
+
+/// How many times the syscalls in a group were made since the summary was last cleared.
+#[derive(Clone, Debug, Default)]
+pub struct SummaryRow {
+    pub count: u64,
+    pub errors: u64,
+
+    /// The times at which the syscalls in this group were first and last seen, in nanoseconds since the epoch. The
+    /// first one is kept across intervals for as long as the group keeps showing up.
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+/// Counts syscalls by process name, syscall, address and result, so a busy host prints a line per group every
+/// interval rather than a line per syscall.
+#[derive(Default)]
+pub struct SummaryTable {
+    rows: HashMap<SummaryGroup, SummaryRow>,
+}
+
+impl SummaryTable {
+    /// Counts a syscall made at the given time, in nanoseconds since the epoch.
+    pub fn record(&mut self, command: &str, event: &SyscallEvent, timestamp: u64) {
+        let address = event.address.as_ref().and_then(group_address);
+        let result = match (event.return_value, event.errno) {
+            (0.., _) => "ok".into(),
+            (_, Some(errno)) => format!("{errno:?}"),
+            (value, None) => value.to_string(),
+        };
+        let group = SummaryGroup { command: command.to_string(), syscall: event.syscall, address, result };
+        self.add(group, 1, event.return_value < 0, timestamp, timestamp);
+    }
+
+    /// Adds a number of syscalls in the same group, the first and last of which were made at the given times.
+    pub fn add(&mut self, group: SummaryGroup, count: u64, failed: bool, first_seen: u64, last_seen: u64) {
+        let row = self.rows.entry(group).or_insert_with(|| SummaryRow { first_seen, ..Default::default() });
+        row.count += count;
+        if failed {
+            row.errors += count;
+        }
+        row.first_seen = row.first_seen.min(first_seen);
+        row.last_seen = row.last_seen.max(last_seen);
+    }
+
+    /// Whether no syscalls were counted since the summary was last cleared.
+    pub fn is_empty(&self) -> bool {
+        self.rows.values().all(|row| row.count == 0)
+    }
+
+    /// Starts counting over, forgetting the groups that weren't seen since the summary was last cleared.
+    pub fn clear(&mut self) {
+        self.rows.retain(|_, row| row.count > 0);
+        for row in self.rows.values_mut() {
+            row.count = 0;
+            row.errors = 0;
+        }
+    }
+}
+
+impl fmt::Display for SummaryTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rows: Vec<_> = self.rows.iter().filter(|(_, row)| row.count > 0).collect();
+        rows.sort_by(|(a, a_row), (b, b_row)| b_row.count.cmp(&a_row.count).then_with(|| a.cmp(b)));
+        let addresses = rows.iter().filter_map(|(group, _)| group.address.as_ref());
+        let width = addresses.map(String::len).max().unwrap_or_default().max("ADDRESS".len());
+        writeln!(
+            f,
+            "{:>8} {:>8} {:<15} {:<15} {:<16} {:<14} {:<width$} RESULT",
+            "COUNT", "ERRORS", "FIRST", "LAST", "PROCESS", "SYSCALL", "ADDRESS"
+        )?;
+        let (mut count, mut errors) = (0, 0);
+        for (group, row) in rows {
+            let SummaryGroup { command, syscall, address, result } = group;
+            let address = address.as_deref().unwrap_or("-");
+            let (first, last) = (TimestampDisplay(row.first_seen), TimestampDisplay(row.last_seen));
+            writeln!(
+                f,
+                "{:>8} {:>8} {first:<15} {last:<15} {command:<16} {syscall:<14} {address:<width$} {result}",
+                row.count, row.errors
+            )?;
+            count += row.count;
+            errors += row.errors;
+        }
+        writeln!(f, "{count:>8} {errors:>8} total")
+    }
+}
+
+/// The address syscalls are grouped by. The local endpoint of connected sockets is left out given it's usually an
+/// ephemeral port.
+fn group_address(address: &SyscallAddress) -> Option<String> {
+    match (&address.address, address.port, &address.path) {
+        (Some(address), Some(port), _) => Some(format!("{address}:{port}")),
+        (_, _, Some(path)) => Some(path.clone()),
+        _ => None,
+    }
+}
+
+// The map's key and value types are shared with the eBPF programs, so they're wrapped to be usable with aya's maps.
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+struct KernelKey(SummaryKey);
+
+unsafe impl Pod for KernelKey {}
+
+// Used to remember the counts read from the map last time.
+impl PartialEq for KernelKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes() == other.bytes()
+    }
+}
+
+impl Eq for KernelKey {}
+
+impl Hash for KernelKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bytes().hash(state);
+    }
+}
+
+impl KernelKey {
+    fn bytes(&self) -> &[u8] {
+        // The key has no padding, so every byte in it is initialized.
+        unsafe { std::slice::from_raw_parts(self as *const Self as *const u8, std::mem::size_of::<Self>()) }
+    }
+}
+
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+struct KernelValue(SummaryValue);
+
+unsafe impl Pod for KernelValue {}
+
+/// The summary the eBPF programs keep when summarizing in the kernel, which counts the syscalls that use an IP address
+/// instead of sending them to userspace.
+pub struct KernelSummary {
+    map: BpfHashMap<MapRefMut, KernelKey, KernelValue>,
+    generation: Array<MapRefMut, u32>,
+    generations: Generations,
+}
+
+impl KernelSummary {
+    pub fn new(bpf: &Bpf) -> anyhow::Result<Self> {
+        let map = BpfHashMap::try_from(bpf.map_mut("SUMMARY")?)?;
+        let generation = Array::try_from(bpf.map_mut("SUMMARY_GENERATION")?)?;
+        Ok(Self { map, generation, generations: Generations::default() })
+    }
+
+    /// Adds the syscalls counted in the kernel since the last time this was called to a table.
+    ///
+    /// The eBPF programs are moved on to a new generation first. Entries are only removed once they're two generations
+    /// old, given the programs that were already running when the generation changed may still count events in the
+    /// previous one, so what was counted in an entry since it was last read is worked out from its previous count.
+    pub fn drain(&mut self, table: &mut SummaryTable, clock: &Clock) -> anyhow::Result<()> {
+        self.generation.set(0, self.generations.advance(), 0)?;
+        let entries = self.map.iter().collect::<Result<Vec<_>, _>>()?;
+        for (key, KernelValue(value)) in entries {
+            let (count, stale) = self.generations.read(key, value.count);
+            if stale {
+                // The entry may be gone already if the map was modified while iterating it.
+                let _ = self.map.remove(&key);
+            }
+            if count == 0 {
+                continue;
+            }
+            let KernelKey(SummaryKey { command, address, errno, port, family, syscall, .. }) = key;
+            let address = parse_address(&family, &address)?;
+            let result = match errno {
+                0 => "ok".into(),
+                errno => {
+                    Errno::from_i32(errno).map(|errno| format!("{errno:?}")).unwrap_or_else(|| (-errno).to_string())
+                }
+            };
+            let group = SummaryGroup {
+                command: parse_command(&command),
+                syscall: syscall_name(&syscall),
+                address: Some(format!("{address}:{}", port.to_be())),
+                result,
+            };
+            let (first_seen, last_seen) = (clock.wall_clock(value.first_seen), clock.wall_clock(value.last_seen));
+            table.add(group, count, errno != 0, first_seen, last_seen);
+        }
+        self.generations.finish();
+        Ok(())
+    }
+}
+
+/// Keeps track of what was already read from the entries of the summary map, given the generations they're in.
+#[derive(Default)]
+struct Generations {
+    // The generation the eBPF programs are counting events in.
+    current: u32,
+    // The count of every entry of the previous generation the last time it was read.
+    counts: HashMap<KernelKey, u64>,
+}
+
+impl Generations {
+    /// Moves on to the next generation, returning it.
+    fn advance(&mut self) -> u32 {
+        self.current = self.current.wrapping_add(1);
+        self.current
+    }
+
+    /// Works out how much was counted in an entry since it was last read, and whether it's old enough to be removed.
+    fn read(&mut self, key: KernelKey, count: u64) -> (u64, bool) {
+        let previous_generation = self.current.wrapping_sub(1);
+        let (previous, stale) = match key.0.generation {
+            generation if generation == self.current => return (0, false),
+            generation if generation == previous_generation => (self.counts.insert(key, count), false),
+            _ => (self.counts.remove(&key), true),
+        };
+        (count.saturating_sub(previous.unwrap_or_default()), stale)
+    }
+
+    /// Forgets the entries that were removed, even if they weren't seen while reading the map.
+    fn finish(&mut self) {
+        let previous_generation = self.current.wrapping_sub(1);
+        self.counts.retain(|key, _| key.0.generation == previous_generation);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sockwho_common::{AddressFamily, Syscall};
+
+    fn key(generation: u32) -> KernelKey {
+        KernelKey(SummaryKey {
+            command: [0; 16],
+            address: [10, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            errno: 0,
+            port: 443u16.to_be(),
+            family: AddressFamily::Ipv4,
+            syscall: Syscall::Connect,
+            generation,
+        })
+    }
+
+    fn group(command: &str) -> SummaryGroup {
+        SummaryGroup { command: command.into(), syscall: "connect", address: None, result: "ok".into() }
+    }
+
+    #[test]
+    fn entries_are_read_until_they_are_two_generations_old() {
+        let mut generations = Generations::default();
+        assert_eq!(generations.advance(), 1);
+        // The entries of the generation that's being counted in are left for later.
+        assert_eq!(generations.read(key(1), 3), (0, false));
+        assert_eq!(generations.read(key(0), 5), (5, false));
+        generations.finish();
+
+        assert_eq!(generations.advance(), 2);
+        // The programs that were running when the generation changed counted two more events in the old one.
+        assert_eq!(generations.read(key(0), 7), (2, true));
+        assert_eq!(generations.read(key(1), 4), (4, false));
+        generations.finish();
+        assert_eq!(generations.counts.len(), 1);
+
+        assert_eq!(generations.advance(), 3);
+        assert_eq!(generations.read(key(1), 4), (0, true));
+        generations.finish();
+        assert!(generations.counts.is_empty());
+    }
+
+    #[test]
+    fn clear_keeps_the_groups_seen_since_the_last_clear() {
+        let mut table = SummaryTable::default();
+        table.add(group("curl"), 2, true, 1000, 2000);
+        table.add(group("wget"), 1, false, 1500, 1500);
+        table.add(group("curl"), 1, true, 500, 1800);
+        assert_eq!(table.rows[&group("curl")].count, 3);
+        assert_eq!(table.rows[&group("curl")].errors, 3);
+        assert_eq!((table.rows[&group("curl")].first_seen, table.rows[&group("curl")].last_seen), (500, 2000));
+
+        table.clear();
+        assert!(table.is_empty());
+        table.add(group("curl"), 1, false, 3000, 3000);
+        table.clear();
+        // The groups that weren't seen in the last interval are forgotten, the others keep when they were first seen.
+        assert!(!table.rows.contains_key(&group("wget")));
+        assert_eq!(table.rows[&group("curl")].first_seen, 500);
+        assert_eq!(table.rows[&group("curl")].count, 0);
+    }
+}
@@ -0,0 +1,416 @@
+use crate::{
+    clock::TimestampDisplay,
+    errno::Errno,
+    event::{Event, EventDetails, StateChangeEvent, SyscallEvent},
+    listeners::{Listener, ListenerTable},
+    processor::TcpState,
+    sink::EventSink,
+};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, VecDeque},
+    fmt,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// How long processes and destinations are kept around for after they were last seen, in nanoseconds.
+const IDLE_TIMEOUT: u64 = 60_000_000_000;
+
+// How long a handshake is waited for before it's assumed the state change that finished it was missed, in nanoseconds.
+const HANDSHAKE_TIMEOUT: u64 = 300_000_000_000;
+
+/// The tables displayed by `sockwho top`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TopTable {
+    Processes,
+    Destinations,
+    Listeners,
+    FailedConnects,
+}
+
+impl TopTable {
+    pub const ALL: [TopTable; 4] = [Self::Processes, Self::Destinations, Self::Listeners, Self::FailedConnects];
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Self::Processes => "Processes",
+            Self::Destinations => "Destinations",
+            Self::Listeners => "Listeners",
+            Self::FailedConnects => "Failed connects",
+        }
+    }
+
+    pub fn columns(&self) -> &'static [&'static str] {
+        match self {
+            Self::Processes => &["PROCESS", "PID", "SYSCALLS", "RATE/s", "ERRORS", "CONNECTS", "LAST"],
+            Self::Destinations => &["DESTINATION", "SYSCALLS", "RATE/s", "FAILED", "PROCESS", "LAST"],
+            Self::Listeners => &["ADDRESS", "PROCESS", "PID", "FD", "BACKLOG"],
+            Self::FailedConnects => &["PROCESS", "DESTINATION", "ERROR", "COUNT", "LAST"],
+        }
+    }
+
+    /// The column the table is sorted by until told otherwise, and whether it's sorted in descending order.
+    pub fn default_sort(&self) -> (usize, bool) {
+        match self {
+            Self::Processes => (3, true),
+            Self::Destinations => (2, true),
+            Self::Listeners => (0, false),
+            Self::FailedConnects => (3, true),
+        }
+    }
+}
+
+/// A value in one of the tables.
+#[derive(Clone, Debug)]
+pub enum Cell {
+    Text(String),
+    Count(Option<u64>),
+    Rate(f64),
+    /// A wall-clock time, in nanoseconds since the epoch.
+    Time(u64),
+}
+
+impl Cell {
+    /// Whether this cell contains some text. Numbers never match.
+    pub fn matches(&self, text: &str) -> bool {
+        match self {
+            Self::Text(value) => value.contains(text),
+            _ => false,
+        }
+    }
+
+    /// Orders cells in the same column.
+    pub fn compare(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Text(a), Self::Text(b)) => a.cmp(b),
+            (Self::Count(a), Self::Count(b)) => a.cmp(b),
+            (Self::Rate(a), Self::Rate(b)) => a.total_cmp(b),
+            (Self::Time(a), Self::Time(b)) => a.cmp(b),
+            _ => Ordering::Equal,
+        }
+    }
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Text(value) => write!(f, "{value}"),
+            Self::Count(Some(value)) => write!(f, "{value}"),
+            Self::Count(None) => write!(f, "-"),
+            Self::Rate(value) => write!(f, "{value:.1}"),
+            Self::Time(value) => write!(f, "{}", TimestampDisplay(*value)),
+        }
+    }
+}
+
+// A counter whose rate is worked out every time the stats are refreshed.
+#[derive(Default)]
+struct Counter {
+    total: u64,
+    previous: u64,
+    rate: f64,
+}
+
+impl Counter {
+    fn refresh(&mut self, elapsed: f64) {
+        if elapsed > 0.0 {
+            self.rate = (self.total - self.previous) as f64 / elapsed;
+        }
+        self.previous = self.total;
+    }
+}
+
+#[derive(Default)]
+struct ProcessStats {
+    command: String,
+    syscalls: Counter,
+    errors: u64,
+    connects: u64,
+    last_seen: u64,
+}
+
+#[derive(Default)]
+struct DestinationStats {
+    syscalls: Counter,
+    failed: u64,
+    command: String,
+    last_seen: u64,
+}
+
+#[derive(Default)]
+struct FailureStats {
+    count: u64,
+    last_seen: u64,
+}
+
+// A TCP handshake that's in progress, along with whether the connect(2) call that started it returned EINPROGRESS.
+// Handshakes that fail while connect(2) blocks are counted from the error it returns instead.
+struct PendingConnect {
+    command: String,
+    tid: u32,
+    started: u64,
+    in_progress: bool,
+}
+
+/// The live statistics displayed by `sockwho top`, built from the events written to a `TopSink`.
+#[derive(Default)]
+pub struct TopStats {
+    processes: HashMap<u32, ProcessStats>,
+    destinations: HashMap<String, DestinationStats>,
+    listeners: ListenerTable,
+    failures: HashMap<(String, String, String), FailureStats>,
+    // Handshakes by network namespace and endpoints, the same way `LatencyTracker` finds them. Concurrent ones between
+    // the same endpoints are assumed to finish in order.
+    connecting: HashMap<(Option<u64>, IpAddr, IpAddr, u16), VecDeque<PendingConnect>>,
+    last_refresh: Option<u64>,
+}
+
+impl TopStats {
+    /// Updates the statistics with an event.
+    pub fn record(&mut self, event: &Event) {
+        match &event.details {
+            EventDetails::Syscall(syscall) => self.record_syscall(event, syscall),
+            EventDetails::StateChange(change) => self.record_state_change(event, change),
+            _ => (),
+        };
+    }
+
+    fn record_syscall(&mut self, event: &Event, syscall: &SyscallEvent) {
+        let (pid, command, timestamp) = (event.process.pid, &event.process.comm, event.timestamp);
+        let tid = thread_id(event);
+        let failed = syscall.return_value < 0;
+        let in_progress = matches!(syscall.errno, Some(Errno::EINPROGRESS));
+        let process = self.processes.entry(pid).or_default();
+        process.command.clone_from(command);
+        process.syscalls.total += 1;
+        process.last_seen = timestamp;
+        if failed && !in_progress {
+            process.errors += 1;
+        }
+        if syscall.syscall == "connect" {
+            process.connects += 1;
+        }
+        if let (Some(address), Some(fd), "bind", false) = (&syscall.address, syscall.fd, syscall.syscall, failed) {
+            self.listeners.bind(pid, fd, address.to_string(), address.family);
+        }
+        if let ("connect" | "send_to" | "send_msg" | "send_mmsg", Some(address)) = (syscall.syscall, &syscall.address) {
+            if let (Some(ip), Some(port)) = (address.address, address.port) {
+                self.record_destination(command, tid, syscall, ip, port, timestamp);
+            }
+        }
+        match (syscall.syscall, syscall.fd, syscall.argument) {
+            ("listen", Some(fd), Some(backlog)) if !failed => {
+                self.listeners.listen(pid, tid, fd, backlog, command);
+            }
+            ("close", Some(fd), _) => {
+                self.listeners.closed(pid, fd);
+            }
+            _ => (),
+        };
+    }
+
+    fn record_destination(
+        &mut self,
+        command: &str,
+        tid: u32,
+        syscall: &SyscallEvent,
+        ip: IpAddr,
+        port: u16,
+        timestamp: u64,
+    ) {
+        let destination = format!("{ip}:{port}");
+        let stats = self.destinations.entry(destination.clone()).or_default();
+        stats.syscalls.total += 1;
+        stats.command = command.to_string();
+        stats.last_seen = timestamp;
+        if syscall.syscall != "connect" || syscall.return_value >= 0 {
+            return;
+        }
+        let error = match syscall.errno {
+            // The handshake is still going on, so whether it failed is only known once the socket's state changes. It
+            // was started by this thread just before, while connect(2) was running.
+            Some(Errno::EINPROGRESS) => {
+                let pending = self
+                    .connecting
+                    .iter_mut()
+                    .filter(|((_, _, address, destination_port), _)| *address == ip && *destination_port == port)
+                    .flat_map(|(_, pending)| pending.iter_mut())
+                    .find(|connect| connect.tid == tid && !connect.in_progress);
+                if let Some(pending) = pending {
+                    pending.in_progress = true;
+                }
+                return;
+            }
+            Some(errno) => format!("{errno:?}"),
+            None => syscall.return_value.to_string(),
+        };
+        stats.failed += 1;
+        self.record_failure(command.to_string(), destination, error, timestamp);
+    }
+
+    fn record_state_change(&mut self, event: &Event, change: &StateChangeEvent) {
+        let StateChangeEvent {
+            source_address,
+            source_port,
+            destination_address,
+            destination_port,
+            old_state,
+            new_state,
+            ..
+        } = change;
+        let address = format!("{source_address}:{source_port}");
+        let (pid, tid) = (event.process.pid, thread_id(event));
+        self.listeners.state_changed(pid, tid, &event.process.comm, address, old_state, new_state);
+        let net_namespace = event.process.identity.as_ref().and_then(|identity| identity.net_namespace);
+        let key = (net_namespace, *source_address, *destination_address, *destination_port);
+        match (old_state, new_state) {
+            (_, TcpState::SynSent) => {
+                let command = event.process.comm.clone();
+                let pending = PendingConnect { command, tid, started: event.timestamp, in_progress: false };
+                self.connecting.entry(key).or_default().push_back(pending);
+                return;
+            }
+            (TcpState::SynSent, TcpState::Established | TcpState::Close) => (),
+            _ => return,
+        };
+        let Some(pending) = self.connecting.get_mut(&key) else {
+            return;
+        };
+        let Some(PendingConnect { command, in_progress, .. }) = pending.pop_front() else {
+            return;
+        };
+        if pending.is_empty() {
+            self.connecting.remove(&key);
+        }
+        if *new_state == TcpState::Established || !in_progress {
+            return;
+        }
+        // The socket was closed during the handshake, e.g. because the connection was refused or timed out.
+        let destination = format!("{destination_address}:{destination_port}");
+        if let Some(stats) = self.destinations.get_mut(&destination) {
+            stats.failed += 1;
+        }
+        self.record_failure(command, destination, "handshake failed".into(), event.timestamp);
+    }
+
+    fn record_failure(&mut self, command: String, destination: String, error: String, timestamp: u64) {
+        let failure = self.failures.entry((command, destination, error)).or_default();
+        failure.count += 1;
+        failure.last_seen = timestamp;
+    }
+
+    /// Works out the rates since the last refresh and forgets the processes and destinations that have been idle for
+    /// a while.
+    pub fn refresh(&mut self) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+        let elapsed = self.last_refresh.map(|last| now.saturating_sub(last) as f64 / 1e9).unwrap_or_default();
+        self.last_refresh = Some(now);
+        let active = |last_seen: u64| last_seen.saturating_add(IDLE_TIMEOUT) > now;
+        self.processes.retain(|_, process| active(process.last_seen));
+        self.destinations.retain(|_, destination| active(destination.last_seen));
+        self.failures.retain(|_, failure| active(failure.last_seen));
+        for process in self.processes.values_mut() {
+            process.syscalls.refresh(elapsed);
+        }
+        for destination in self.destinations.values_mut() {
+            destination.syscalls.refresh(elapsed);
+        }
+        self.connecting.retain(|_, pending| {
+            pending.retain(|connect| connect.started.saturating_add(HANDSHAKE_TIMEOUT) > now);
+            !pending.is_empty()
+        });
+    }
+
+    /// Forgets all the counters, keeping the listeners and the connects in progress.
+    pub fn reset(&mut self) {
+        self.processes.clear();
+        self.destinations.clear();
+        self.failures.clear();
+    }
+
+    /// The rows in one of the tables, in no particular order.
+    pub fn rows(&self, table: TopTable) -> Vec<Vec<Cell>> {
+        match table {
+            TopTable::Processes => self
+                .processes
+                .iter()
+                .map(|(pid, process)| {
+                    vec![
+                        Cell::Text(process.command.clone()),
+                        Cell::Text(pid.to_string()),
+                        Cell::Count(Some(process.syscalls.total)),
+                        Cell::Rate(process.syscalls.rate),
+                        Cell::Count(Some(process.errors)),
+                        Cell::Count(Some(process.connects)),
+                        Cell::Time(process.last_seen),
+                    ]
+                })
+                .collect(),
+            TopTable::Destinations => self
+                .destinations
+                .iter()
+                .map(|(destination, stats)| {
+                    vec![
+                        Cell::Text(destination.clone()),
+                        Cell::Count(Some(stats.syscalls.total)),
+                        Cell::Rate(stats.syscalls.rate),
+                        Cell::Count(Some(stats.failed)),
+                        Cell::Text(stats.command.clone()),
+                        Cell::Time(stats.last_seen),
+                    ]
+                })
+                .collect(),
+            TopTable::Listeners => self
+                .listeners
+                .iter()
+                .map(|(address, Listener { pid, command, fd, backlog })| {
+                    vec![
+                        Cell::Text(address.clone()),
+                        Cell::Text(command.clone()),
+                        Cell::Text(pid.to_string()),
+                        Cell::Count(fd.map(u64::from)),
+                        Cell::Count(backlog.and_then(|backlog| u64::try_from(backlog).ok())),
+                    ]
+                })
+                .collect(),
+            TopTable::FailedConnects => self
+                .failures
+                .iter()
+                .map(|((command, destination, error), failure)| {
+                    vec![
+                        Cell::Text(command.clone()),
+                        Cell::Text(destination.clone()),
+                        Cell::Text(error.clone()),
+                        Cell::Count(Some(failure.count)),
+                        Cell::Time(failure.last_seen),
+                    ]
+                })
+                .collect(),
+        }
+    }
+}
+
+// Only the events generated by the socket probes know the thread, the rest are assumed to come from the main one.
+fn thread_id(event: &Event) -> u32 {
+    event.process.identity.as_ref().map(|identity| identity.tid).unwrap_or(event.process.pid)
+}
+
+/// Feeds the events it's given into the statistics displayed by `sockwho top`.
+pub struct TopSink {
+    stats: Arc<Mutex<TopStats>>,
+}
+
+impl TopSink {
+    pub fn new(stats: Arc<Mutex<TopStats>>) -> Self {
+        Self { stats }
+    }
+}
+
+impl EventSink for TopSink {
+    fn write(&mut self, event: &Event) -> anyhow::Result<()> {
+        self.stats.lock().expect("poisoned lock").record(event);
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::{anyhow, Context};
+use sockwho_common::NewTaskOffsets;
+use std::fs;
+
+// tracefs is mounted on its own since 4.1, and under debugfs before that.
+const TRACEFS_PATHS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+/// The format of a tracepoint's records, as described by its `format` file in tracefs.
+pub struct TracepointFormat {
+    format: String,
+}
+
+impl TracepointFormat {
+    pub fn read(category: &str, name: &str) -> anyhow::Result<Self> {
+        let format = TRACEFS_PATHS
+            .iter()
+            .find_map(|root| fs::read_to_string(format!("{root}/events/{category}/{name}/format")).ok())
+            .ok_or_else(|| anyhow!("tracepoint {category}:{name} not found, is tracefs mounted?"))?;
+        Ok(Self { format })
+    }
+
+    /// The offset of a field in the tracepoint's records, in bytes.
+    ///
+    /// Fields are described by lines like `field:pid_t pid; offset:8; size:4; signed:1;`, separated by tabs.
+    pub fn field_offset(&self, field: &str) -> anyhow::Result<u32> {
+        for line in self.format.lines() {
+            let mut attributes = line.split(';').map(str::trim);
+            let Some(declaration) = attributes.next().and_then(|a| a.strip_prefix("field:")) else {
+                continue;
+            };
+            // The name is the last word of the declaration, minus the length of arrays.
+            let name = declaration.rsplit(' ').next().unwrap_or_default();
+            if name.split('[').next() != Some(field) {
+                continue;
+            }
+            let offset = attributes
+                .find_map(|a| a.strip_prefix("offset:"))
+                .ok_or_else(|| anyhow!("field {field} has no offset"))?;
+            return offset.parse().with_context(|| format!("invalid offset for field {field}"));
+        }
+        Err(anyhow!("field {field} not found"))
+    }
+}
+
+/// Finds the offsets of the fields of the `task_newtask` tracepoint read by the eBPF programs.
+pub fn new_task_offsets() -> anyhow::Result<NewTaskOffsets> {
+    let format = TracepointFormat::read("task", "task_newtask")?;
+    Ok(NewTaskOffsets { pid: format.field_offset("pid")?, clone_flags: format.field_offset("clone_flags")? })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The format of task:task_newtask on 6.1.
+    const TASK_NEWTASK: &str = "name: task_newtask
+ID: 311
+format:
+\tfield:unsigned short common_type;\toffset:0;\tsize:2;\tsigned:0;
+\tfield:unsigned char common_flags;\toffset:2;\tsize:1;\tsigned:0;
+\tfield:unsigned char common_preempt_count;\toffset:3;\tsize:1;\tsigned:0;
+\tfield:int common_pid;\toffset:4;\tsize:4;\tsigned:1;
+
+\tfield:pid_t pid;\toffset:8;\tsize:4;\tsigned:1;
+\tfield:char comm[16];\toffset:12;\tsize:16;\tsigned:0;
+\tfield:unsigned long clone_flags;\toffset:32;\tsize:8;\tsigned:0;
+\tfield:short oom_score_adj;\toffset:40;\tsize:2;\tsigned:1;
+
+print fmt: \"pid=%d comm=%s clone_flags=%lx oom_score_adj=%hd\", REC->pid, REC->comm, REC->clone_flags, REC->oom_score_adj
+";
+
+    fn format() -> TracepointFormat {
+        TracepointFormat { format: TASK_NEWTASK.into() }
+    }
+
+    #[test]
+    fn field_offset_finds_fields_by_name() {
+        assert_eq!(format().field_offset("pid").unwrap(), 8);
+        assert_eq!(format().field_offset("clone_flags").unwrap(), 32);
+        assert_eq!(format().field_offset("common_pid").unwrap(), 4);
+    }
+
+    #[test]
+    fn field_offset_ignores_array_lengths() {
+        assert_eq!(format().field_offset("comm").unwrap(), 12);
+    }
+
+    #[test]
+    fn field_offset_rejects_unknown_fields() {
+        assert!(format().field_offset("flags").is_err());
+        assert!(format().field_offset("REC->pid").is_err());
+    }
+}
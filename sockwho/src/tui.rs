@@ -0,0 +1,179 @@
+use crate::top::{Cell, TopStats, TopTable};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Style, Stylize},
+    text::Line,
+    widgets::{Block, Row, Table},
+    DefaultTerminal, Frame,
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const HELP: &str = "q quit  tab switch table  ←/→ sort column  r reverse  / filter  esc clear filter  c reset";
+
+/// Runs the `sockwho top` terminal UI until the user quits, refreshing the tables at the given interval.
+pub fn run(stats: Arc<Mutex<TopStats>>, interval: Duration) -> anyhow::Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let result = View::new().run(&mut terminal, &stats, interval);
+    ratatui::restore();
+    result
+}
+
+/// What the user is looking at.
+struct View {
+    focused: usize,
+    // The column every table is sorted by and whether it's in descending order.
+    sorting: Vec<(usize, bool)>,
+    filter: String,
+    editing_filter: bool,
+}
+
+impl View {
+    fn new() -> Self {
+        let sorting = TopTable::ALL.iter().map(TopTable::default_sort).collect();
+        Self { focused: 0, sorting, filter: String::new(), editing_filter: false }
+    }
+
+    fn run(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        stats: &Mutex<TopStats>,
+        interval: Duration,
+    ) -> anyhow::Result<()> {
+        let mut next_refresh = Instant::now();
+        loop {
+            let now = Instant::now();
+            if now >= next_refresh {
+                stats.lock().expect("poisoned lock").refresh();
+                next_refresh = now + interval;
+            }
+            let tables = {
+                let stats = stats.lock().expect("poisoned lock");
+                TopTable::ALL.map(|table| stats.rows(table))
+            };
+            terminal.draw(|frame| self.draw(frame, tables))?;
+            if !event::poll(next_refresh.saturating_duration_since(Instant::now()))? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match self.handle_key(key) {
+                Action::Quit => return Ok(()),
+                Action::Reset => stats.lock().expect("poisoned lock").reset(),
+                Action::None => (),
+            };
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Action {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Action::Quit;
+        }
+        if self.editing_filter {
+            match key.code {
+                KeyCode::Char(c) => self.filter.push(c),
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                }
+                KeyCode::Enter => self.editing_filter = false,
+                KeyCode::Esc => {
+                    self.filter.clear();
+                    self.editing_filter = false;
+                }
+                _ => (),
+            };
+            return Action::None;
+        }
+        let columns = TopTable::ALL[self.focused].columns().len();
+        let (column, descending) = &mut self.sorting[self.focused];
+        match key.code {
+            KeyCode::Char('q') => return Action::Quit,
+            KeyCode::Char('c') => return Action::Reset,
+            KeyCode::Char('/') => self.editing_filter = true,
+            KeyCode::Char('r') => *descending = !*descending,
+            KeyCode::Left => *column = (*column + columns - 1) % columns,
+            KeyCode::Right => *column = (*column + 1) % columns,
+            KeyCode::Tab => self.focused = (self.focused + 1) % TopTable::ALL.len(),
+            KeyCode::BackTab => self.focused = (self.focused + TopTable::ALL.len() - 1) % TopTable::ALL.len(),
+            KeyCode::Esc => self.filter.clear(),
+            _ => (),
+        };
+        Action::None
+    }
+
+    fn draw(&self, frame: &mut Frame, tables: [Vec<Vec<Cell>>; 4]) {
+        let [grid, status] = Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).areas(frame.area());
+        let [top, bottom] = Layout::vertical([Constraint::Fill(1); 2]).areas(grid);
+        let [top_left, top_right] = Layout::horizontal([Constraint::Fill(1); 2]).areas(top);
+        let [bottom_left, bottom_right] = Layout::horizontal([Constraint::Fill(1); 2]).areas(bottom);
+        let areas = [top_left, top_right, bottom_left, bottom_right];
+        for (index, rows) in tables.into_iter().enumerate() {
+            self.draw_table(frame, areas[index], index, rows);
+        }
+        let status_line = match (self.editing_filter, self.filter.is_empty()) {
+            (true, _) => Line::from(format!("filter: {}_", self.filter)),
+            (false, true) => Line::from(HELP).dim(),
+            (false, false) => Line::from(format!("filter: {}  {HELP}", self.filter)),
+        };
+        frame.render_widget(status_line, status);
+    }
+
+    fn draw_table(&self, frame: &mut Frame, area: Rect, index: usize, mut rows: Vec<Vec<Cell>>) {
+        let table = TopTable::ALL[index];
+        let (column, descending) = self.sorting[index];
+        if !self.filter.is_empty() {
+            rows.retain(|row| row.iter().any(|cell| cell.matches(&self.filter)));
+        }
+        rows.sort_by(|a, b| {
+            let ordering = a[column].compare(&b[column]);
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        let header: Vec<_> = table
+            .columns()
+            .iter()
+            .enumerate()
+            .map(|(i, name)| match (i == column, descending) {
+                (true, true) => format!("{name}▼"),
+                (true, false) => format!("{name}▲"),
+                (false, _) => name.to_string(),
+            })
+            .collect();
+        let rows: Vec<Vec<String>> = rows.iter().map(|row| row.iter().map(ToString::to_string).collect()).collect();
+        let widths: Vec<_> = header
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let width = rows.iter().map(|row| row[i].chars().count()).max().unwrap_or_default();
+                Constraint::Length(width.max(name.chars().count()) as u16)
+            })
+            .collect();
+        let title = format!(" {} ({}) ", table.title(), rows.len());
+        let block = match index == self.focused {
+            true => Block::bordered().title(title.bold()).border_style(Style::new().yellow()),
+            false => Block::bordered().title(title),
+        };
+        let widget = Table::new(rows.into_iter().map(Row::new), widths)
+            .header(Row::new(header).bold())
+            .column_spacing(2)
+            .block(block);
+        frame.render_widget(widget, area);
+    }
+}
+
+/// What the UI needs to do after handling a key press.
+enum Action {
+    None,
+    Quit,
+    Reset,
+}